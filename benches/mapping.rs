@@ -0,0 +1,17 @@
+//! Times the row mappers of `src/mapping.rs` against each other. The mappers
+//! aren't part of the library: this runs `bench-mapping` on the binary built
+//! with the bench profile, against `DATABASE_URL`.
+//!
+//! ```bash
+//! cargo bench --bench mapping
+//! ```
+
+use std::process::Command;
+
+fn main() {
+    let status = Command::new(env!("CARGO_BIN_EXE_sqlx-pb"))
+        .args(["bench-mapping", "--rows", "10000", "--iterations", "10"])
+        .status()
+        .expect("failed to run sqlx-pb!");
+    assert!(status.success(), "bench-mapping failed");
+}
@@ -0,0 +1,3 @@
+ALTER TABLE jobs ADD COLUMN tags TEXT[] NOT NULL DEFAULT '{}';
+
+CREATE INDEX jobs_tags_idx ON jobs USING GIN (tags);
@@ -0,0 +1 @@
+ALTER TABLE jobs ADD COLUMN metadata JSONB NOT NULL DEFAULT '{}';
@@ -0,0 +1,4 @@
+-- Handled jobs are marked `Done` by the worker, instead of being left
+-- `Running`: they stay around for `tree`, stats and workflows to look at,
+-- and can't be mistaken for jobs in progress or abandoned ones.
+ALTER TYPE JOB_STATUS ADD VALUE IF NOT EXISTS 'Done';
@@ -0,0 +1,3 @@
+ALTER TABLE jobs ADD COLUMN correlation_id TEXT;
+
+CREATE INDEX jobs_correlation_id_idx ON jobs (correlation_id);
@@ -0,0 +1,5 @@
+ALTER TABLE jobs ADD PRIMARY KEY (id);
+
+ALTER TABLE jobs ADD COLUMN parent_job_id BIGINT REFERENCES jobs (id);
+
+CREATE INDEX jobs_parent_job_id_idx ON jobs (parent_job_id);
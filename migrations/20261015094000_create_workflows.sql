@@ -0,0 +1,15 @@
+CREATE TYPE WORKFLOW_STATUS AS ENUM ('Running', 'Completed', 'Failed');
+
+CREATE TABLE workflows (
+    id           BIGINT          NOT NULL GENERATED ALWAYS AS IDENTITY PRIMARY KEY,
+    name         TEXT            NOT NULL,
+    status       WORKFLOW_STATUS NOT NULL DEFAULT 'Running',
+    current_step INT             NOT NULL DEFAULT 0,
+    steps        JSONB           NOT NULL
+);
+
+ALTER TABLE jobs ADD COLUMN run_at TIMESTAMPTZ NOT NULL DEFAULT now();
+ALTER TABLE jobs ADD COLUMN workflow_id BIGINT REFERENCES workflows (id);
+ALTER TABLE jobs ADD COLUMN workflow_step INT;
+
+CREATE INDEX jobs_workflow_id_idx ON jobs (workflow_id);
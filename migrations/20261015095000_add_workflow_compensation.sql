@@ -0,0 +1,4 @@
+ALTER TYPE WORKFLOW_STATUS ADD VALUE 'Compensating';
+ALTER TYPE WORKFLOW_STATUS ADD VALUE 'Compensated';
+
+ALTER TABLE jobs ADD COLUMN workflow_compensation BOOLEAN NOT NULL DEFAULT false;
@@ -0,0 +1,2 @@
+ALTER TABLE jobs ADD COLUMN attempts INT NOT NULL DEFAULT 0;
+ALTER TABLE jobs ADD COLUMN locked_until TIMESTAMPTZ;
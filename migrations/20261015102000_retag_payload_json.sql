@@ -0,0 +1,55 @@
+-- Rewrites an externally tagged enum (`"Variant"` or `{"Variant": content}`, serde's
+-- default representation) into an internally tagged one (`{"type": "Variant", ...content}`)
+-- when content_key is NULL, or an adjacently tagged one (`{"type": "Variant", content_key: content}`)
+-- otherwise. Values already carrying a "type" key are left untouched.
+CREATE FUNCTION retag_enum_json(value JSONB, content_key TEXT) RETURNS JSONB AS $$
+    SELECT CASE
+        WHEN jsonb_typeof(value) = 'string' THEN
+            jsonb_build_object('type', value)
+        WHEN jsonb_typeof(value) = 'object' AND NOT value ? 'type' AND (SELECT count(*) FROM jsonb_object_keys(value)) = 1 THEN
+            (
+                SELECT CASE
+                    WHEN content_key IS NULL THEN jsonb_build_object('type', variant.key) || variant.content
+                    ELSE jsonb_build_object('type', variant.key, content_key, variant.content)
+                END
+                FROM jsonb_each(value) AS variant(key, content)
+            )
+        ELSE value
+    END
+$$ LANGUAGE SQL IMMUTABLE;
+
+-- Compat shim: producers still emitting the old representation keep working.
+CREATE FUNCTION jobs_retag_payload() RETURNS TRIGGER AS $$
+BEGIN
+    NEW.payload := retag_enum_json(NEW.payload, NULL);
+    NEW.params := retag_enum_json(NEW.params, 'data');
+    RETURN NEW;
+END
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER jobs_retag_payload
+    BEFORE INSERT OR UPDATE OF payload, params ON jobs
+    FOR EACH ROW EXECUTE FUNCTION jobs_retag_payload();
+
+UPDATE jobs
+SET payload = retag_enum_json(payload, NULL),
+    params = retag_enum_json(params, 'data');
+
+UPDATE workflows
+SET steps = (
+    SELECT jsonb_agg(
+        step || jsonb_build_object(
+            'payload', retag_enum_json(step->'payload', NULL),
+            'params', retag_enum_json(step->'params', 'data'),
+            'compensation', CASE jsonb_typeof(step->'compensation')
+                WHEN 'object' THEN jsonb_build_object(
+                    'payload', retag_enum_json(step->'compensation'->'payload', NULL),
+                    'params', retag_enum_json(step->'compensation'->'params', 'data')
+                )
+                ELSE step->'compensation'
+            END
+        )
+        ORDER BY position
+    )
+    FROM jsonb_array_elements(steps) WITH ORDINALITY AS s(step, position)
+);
@@ -0,0 +1,7 @@
+CREATE TABLE backfills (
+    name         TEXT        NOT NULL PRIMARY KEY,
+    last_id      BIGINT      NOT NULL DEFAULT 0,
+    updated_rows BIGINT      NOT NULL DEFAULT 0,
+    started_at   TIMESTAMPTZ NOT NULL DEFAULT now(),
+    finished_at  TIMESTAMPTZ
+);
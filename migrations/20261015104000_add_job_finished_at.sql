@@ -0,0 +1,3 @@
+ALTER TABLE jobs ADD COLUMN finished_at TIMESTAMPTZ;
+
+CREATE INDEX jobs_finished_at_idx ON jobs (finished_at);
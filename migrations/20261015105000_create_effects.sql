@@ -0,0 +1,8 @@
+-- Side effects a job already performed (an email sent...), skipped on retry.
+CREATE TABLE effects (
+    job_id     BIGINT      NOT NULL REFERENCES jobs (id) ON DELETE CASCADE,
+    key        TEXT        NOT NULL,
+    result     JSONB       NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    PRIMARY KEY (job_id, key)
+);
@@ -0,0 +1,27 @@
+ALTER TABLE jobs ADD COLUMN queue TEXT NOT NULL DEFAULT 'default';
+
+CREATE INDEX jobs_queue_status_idx ON jobs (queue, status);
+
+-- Queues only need a row here once their settings differ from the defaults.
+CREATE TABLE queues (
+    name     TEXT    NOT NULL PRIMARY KEY,
+    draining BOOLEAN NOT NULL DEFAULT false
+);
+
+-- A draining queue takes no new jobs, so that the ones it holds can run out.
+-- Jobs enqueued by other jobs or by running workflows are still let in: they
+-- belong to work the queue already accepted.
+CREATE FUNCTION jobs_reject_draining() RETURNS TRIGGER AS $$
+BEGIN
+    IF NEW.parent_job_id IS NULL
+       AND NEW.workflow_id IS NULL
+       AND EXISTS (SELECT 1 FROM queues WHERE name = NEW.queue AND draining) THEN
+        RAISE EXCEPTION 'queue "%" is draining, it takes no new jobs', NEW.queue;
+    END IF;
+    RETURN NEW;
+END
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER jobs_reject_draining
+    BEFORE INSERT ON jobs
+    FOR EACH ROW EXECUTE FUNCTION jobs_reject_draining();
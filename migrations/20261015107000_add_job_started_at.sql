@@ -0,0 +1,2 @@
+-- When the latest attempt of the job started, set on claim.
+ALTER TABLE jobs ADD COLUMN started_at TIMESTAMPTZ;
@@ -0,0 +1,6 @@
+-- The payload's enum tag, kept in sync by Postgres, for filters and stats by
+-- type to use the index instead of reading the JSON of every row. Computed
+-- after the retagging trigger, so legacy payloads get their type too.
+ALTER TABLE jobs ADD COLUMN job_type TEXT GENERATED ALWAYS AS (payload->>'type') STORED;
+
+CREATE INDEX jobs_job_type_status_idx ON jobs (job_type, status);
@@ -0,0 +1,5 @@
+CREATE TYPE ERROR_KIND AS ENUM ('Retryable', 'Permanent', 'Timeout', 'Panic');
+
+-- Written on every failure, retried or not, and kept once the job succeeds.
+ALTER TABLE jobs ADD COLUMN last_error TEXT;
+ALTER TABLE jobs ADD COLUMN error_kind ERROR_KIND;
@@ -0,0 +1,4 @@
+-- Jobs enqueued before this migration get the time it ran.
+ALTER TABLE jobs ADD COLUMN created_at TIMESTAMPTZ NOT NULL DEFAULT now();
+
+CREATE INDEX jobs_created_at_idx ON jobs (created_at);
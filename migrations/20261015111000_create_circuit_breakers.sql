@@ -0,0 +1,10 @@
+-- One row per job type whose circuit breaker ever opened. Jobs of that type
+-- aren't claimed until `open_until`, the row is kept afterwards so that only
+-- the attempts made since then count towards the next decision.
+CREATE TABLE circuit_breakers (
+    job_type     TEXT        NOT NULL PRIMARY KEY,
+    opened_at    TIMESTAMPTZ NOT NULL,
+    open_until   TIMESTAMPTZ NOT NULL,
+    failure_rate FLOAT8      NOT NULL,
+    jobs         BIGINT      NOT NULL
+);
@@ -0,0 +1,5 @@
+-- Jobs sharing a key (e.g. `user:42`) never run at the same time.
+ALTER TABLE jobs ADD COLUMN concurrency_key TEXT;
+
+-- Claiming looks for a running job with the same key.
+CREATE INDEX jobs_running_concurrency_key_idx ON jobs (concurrency_key) WHERE status = 'Running';
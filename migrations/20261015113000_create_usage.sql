@@ -0,0 +1,15 @@
+ALTER TABLE jobs ADD COLUMN tenant TEXT NOT NULL DEFAULT 'default';
+
+-- One row per attempt that ran to its end, whatever its outcome. There is no
+-- foreign key to `jobs`: usage is still accounted for once jobs are deleted.
+CREATE TABLE usage (
+    id              BIGINT      NOT NULL GENERATED ALWAYS AS IDENTITY PRIMARY KEY,
+    job_id          BIGINT      NOT NULL,
+    tenant          TEXT        NOT NULL,
+    job_type        TEXT        NOT NULL,
+    attempt         INTEGER     NOT NULL,
+    runtime_seconds FLOAT8      NOT NULL,
+    recorded_at     TIMESTAMPTZ NOT NULL DEFAULT clock_timestamp()
+);
+
+CREATE INDEX usage_recorded_at_idx ON usage (recorded_at);
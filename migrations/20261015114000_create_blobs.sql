@@ -0,0 +1,8 @@
+-- Binary content jobs refer to, rather than carrying it in their payload. A
+-- job records the SHA-256 of each blob it uses when it is enqueued, and the
+-- handler checks it before using the content.
+CREATE TABLE blobs (
+    id         BIGINT      NOT NULL GENERATED ALWAYS AS IDENTITY PRIMARY KEY,
+    content    BYTEA       NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
@@ -0,0 +1,29 @@
+-- Every claim made by a worker running with `--check-claims`. A claim stays
+-- open until its job leaves `Running`, whatever the reason (done, failed,
+-- released, reaped): the job may only be claimed again after that, and a
+-- second open claim for the same job means the claim query is broken.
+CREATE TABLE claims (
+    id         BIGINT      NOT NULL GENERATED ALWAYS AS IDENTITY PRIMARY KEY,
+    job_id     BIGINT      NOT NULL,
+    worker_id  TEXT        NOT NULL,
+    attempt    INTEGER     NOT NULL,
+    claimed_at TIMESTAMPTZ NOT NULL DEFAULT clock_timestamp(),
+    ended_at   TIMESTAMPTZ
+);
+
+CREATE UNIQUE INDEX claims_open_job_id_idx ON claims (job_id) WHERE ended_at IS NULL;
+
+-- Closing claims from a trigger, rather than from the worker, doesn't rely on
+-- the very code being checked.
+CREATE FUNCTION jobs_end_claims() RETURNS TRIGGER AS $$
+BEGIN
+    UPDATE claims SET ended_at = clock_timestamp() WHERE job_id = NEW.id AND ended_at IS NULL;
+    RETURN NULL;
+END
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER jobs_end_claims
+    AFTER UPDATE OF status ON jobs
+    FOR EACH ROW
+    WHEN (OLD.status = 'Running' AND NEW.status <> 'Running')
+    EXECUTE FUNCTION jobs_end_claims();
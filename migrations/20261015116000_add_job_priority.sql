@@ -0,0 +1,6 @@
+-- Due jobs are claimed highest priority first, then oldest first. Workers
+-- started with a minimum priority only take jobs at or above it: capacity kept
+-- for urgent jobs, however many others are due.
+ALTER TABLE jobs ADD COLUMN priority INTEGER NOT NULL DEFAULT 0;
+
+CREATE INDEX jobs_queued_priority_idx ON jobs (priority DESC, id) WHERE status = 'Queued';
@@ -0,0 +1,11 @@
+-- Jobs that failed for good are deleted by `maintain` once past their
+-- retention, unless kept, e.g. while they are being investigated.
+ALTER TABLE jobs ADD COLUMN keep BOOLEAN NOT NULL DEFAULT false;
+
+-- Expired jobs moved out of `jobs` by `maintain --archive`, along with their
+-- effects, as JSON: they don't have to follow later schema changes.
+CREATE TABLE archived_jobs (
+    job_id      BIGINT      NOT NULL PRIMARY KEY,
+    job         JSONB       NOT NULL,
+    archived_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
@@ -0,0 +1,17 @@
+-- What the jobs enqueued in a queue get unless the enqueuer says otherwise,
+-- NULL where the queue has no default. Settings are copied into each job at
+-- enqueue: changing them leaves the jobs already enqueued alone.
+CREATE TABLE queue_settings (
+    queue              TEXT    NOT NULL PRIMARY KEY,
+    priority           INTEGER,
+    max_attempts       INTEGER CHECK (max_attempts > 0),
+    retry_backoff_secs FLOAT8  CHECK (retry_backoff_secs >= 0),
+    timeout_secs       FLOAT8  CHECK (timeout_secs > 0),
+    tenant             TEXT
+);
+
+-- A job's own retry policy and timeout, NULL falling back to the worker's.
+ALTER TABLE jobs
+    ADD COLUMN max_attempts       INTEGER CHECK (max_attempts > 0),
+    ADD COLUMN retry_backoff_secs FLOAT8  CHECK (retry_backoff_secs >= 0),
+    ADD COLUMN timeout_secs       FLOAT8  CHECK (timeout_secs > 0);
@@ -0,0 +1,15 @@
+-- Recurring periods during which the jobs of a queue aren't claimed, e.g. to
+-- keep batch jobs out of business-hours peaks. A window opens whenever its
+-- `cron` expression (5 fields, UTC) matches, and stays open `duration_secs`.
+-- The scheduler keeps `opens_at` and `closes_at` on its current or next
+-- occurrence, claiming only ever looks at those.
+CREATE TABLE maintenance_windows (
+    id            BIGINT      NOT NULL GENERATED ALWAYS AS IDENTITY PRIMARY KEY,
+    queue         TEXT        NOT NULL,
+    cron          TEXT        NOT NULL,
+    duration_secs FLOAT8      NOT NULL CHECK (duration_secs > 0),
+    opens_at      TIMESTAMPTZ NOT NULL,
+    closes_at     TIMESTAMPTZ NOT NULL
+);
+
+CREATE INDEX maintenance_windows_queue_idx ON maintenance_windows (queue);
@@ -0,0 +1,5 @@
+-- A failed job expires even when some other job was enqueued by it: its
+-- children lose their parent instead of keeping it around forever.
+ALTER TABLE jobs
+    DROP CONSTRAINT jobs_parent_job_id_fkey,
+    ADD CONSTRAINT jobs_parent_job_id_fkey FOREIGN KEY (parent_job_id) REFERENCES jobs (id) ON DELETE SET NULL;
@@ -0,0 +1,38 @@
+-- How producers write the payloads of a job type, when not internally tagged
+-- (`{"type": "Variant", ...content}`). Payloads are stored internally tagged
+-- whatever the format they came in: `job_type`, its index and the workers
+-- only know that one.
+CREATE TABLE payload_formats (
+    job_type       TEXT NOT NULL PRIMARY KEY,
+    representation TEXT NOT NULL CHECK (representation IN ('adjacent'))
+);
+
+-- Rewrites a payload into its stored form: externally tagged ones
+-- (`{"Variant": content}`) always, adjacently tagged ones
+-- (`{"type": "Variant", "data": content}`) when their type is configured so.
+-- Adjacent tagging is opt-in as it is ambiguous: a variant with a single field
+-- named "data" looks the same. The CASE keeps jsonb_object_keys away from
+-- payloads that aren't objects.
+CREATE FUNCTION normalize_payload(payload JSONB) RETURNS JSONB AS $$
+    SELECT CASE
+        WHEN jsonb_typeof(retagged->'data') IS DISTINCT FROM 'object' THEN retagged
+        WHEN (SELECT count(*) FROM jsonb_object_keys(retagged)) = 2
+             AND EXISTS (
+                 SELECT 1 FROM payload_formats
+                 WHERE job_type = retagged->>'type' AND representation = 'adjacent'
+             ) THEN
+            (retagged->'data') || jsonb_build_object('type', retagged->'type')
+        ELSE retagged
+    END
+    FROM (SELECT retag_enum_json(payload, NULL) AS retagged) AS payload
+$$ LANGUAGE SQL STABLE;
+
+-- Params hold newtype variants, which can't be internally tagged: they are
+-- always adjacently tagged.
+CREATE OR REPLACE FUNCTION jobs_retag_payload() RETURNS TRIGGER AS $$
+BEGIN
+    NEW.payload := normalize_payload(NEW.payload);
+    NEW.params := retag_enum_json(NEW.params, 'data');
+    RETURN NEW;
+END
+$$ LANGUAGE plpgsql;
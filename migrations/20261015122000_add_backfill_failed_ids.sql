@@ -0,0 +1,4 @@
+-- Jobs a backfill's transform failed on, retried on its next run. A backfill
+-- is only finished once none is left.
+ALTER TABLE backfills
+    ADD COLUMN failed_ids BIGINT[] NOT NULL DEFAULT '{}';
@@ -0,0 +1,56 @@
+-- What happened to each job, appended to and never updated: the row only
+-- holds its latest attempt. Events are the state changes, recorded by the
+-- trigger below whatever made them. Logs are what handlers log, recorded by
+-- the worker as each attempt ends, so lines logged by an attempt whose worker
+-- died are lost.
+CREATE TABLE job_events (
+    id      BIGINT      NOT NULL GENERATED ALWAYS AS IDENTITY PRIMARY KEY,
+    job_id  BIGINT      NOT NULL REFERENCES jobs (id) ON DELETE CASCADE,
+    attempt INT         NOT NULL,
+    kind    TEXT        NOT NULL CHECK (kind IN ('event', 'log')),
+    message TEXT        NOT NULL,
+    at      TIMESTAMPTZ NOT NULL DEFAULT clock_timestamp()
+);
+
+CREATE INDEX job_events_job_id ON job_events (job_id, id);
+
+CREATE FUNCTION jobs_record_event() RETURNS TRIGGER AS $$
+DECLARE
+    message TEXT;
+BEGIN
+    IF TG_OP = 'INSERT' THEN
+        message := format('enqueued in queue %L', NEW.queue)
+            || COALESCE(' by job #' || NEW.parent_job_id, '');
+    ELSIF NEW.status IS DISTINCT FROM OLD.status THEN
+        message := CASE NEW.status
+            WHEN 'Running' THEN format('attempt %s claimed', NEW.attempts)
+            WHEN 'Queued' THEN 'queued again'
+            WHEN 'Done' THEN 'done'
+            WHEN 'Failed' THEN 'failed for good'
+        END
+            || CASE WHEN NEW.last_error IS DISTINCT FROM OLD.last_error
+                   THEN COALESCE(', after ' || NEW.error_kind || ': ' || NEW.last_error, '')
+                   ELSE ''
+               END
+            || CASE WHEN NEW.status = 'Queued' AND NEW.run_at > now()
+                   THEN format(', due at %s', NEW.run_at)
+                   ELSE ''
+               END;
+    ELSIF NEW.started_at IS DISTINCT FROM OLD.started_at THEN
+        message := format('attempt %s started', NEW.attempts);
+    -- `fail` records the error before it changes the status.
+    ELSIF NEW.last_error IS DISTINCT FROM OLD.last_error AND NEW.last_error IS NOT NULL THEN
+        message := format('attempt %s failed, %s: %s', NEW.attempts, NEW.error_kind, NEW.last_error);
+    ELSE
+        RETURN NULL;
+    END IF;
+
+    INSERT INTO job_events (job_id, attempt, kind, message)
+    VALUES (NEW.id, NEW.attempts, 'event', message);
+    RETURN NULL;
+END
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER jobs_record_event
+AFTER INSERT OR UPDATE OF status, started_at, last_error ON jobs
+FOR EACH ROW EXECUTE FUNCTION jobs_record_event();
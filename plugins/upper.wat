@@ -0,0 +1,38 @@
+;; Upper-cases the ASCII letters of its input: the smallest plugin there is,
+;; written in the text format so that it needs no toolchain. A JSON string
+;; input comes back as a JSON string.
+(module
+  (import "host" "log" (func $log (param i32 i32)))
+  (import "host" "output" (func $output (param i32 i32)))
+
+  (memory (export "memory") 1)
+  (data (i32.const 0) "upper-casing the input")
+
+  ;; Each run gets a fresh instance: a bump allocator is all it takes.
+  (global $next (mut i32) (i32.const 1024))
+
+  (func (export "alloc") (param $len i32) (result i32)
+    (local $ptr i32)
+    (local.set $ptr (global.get $next))
+    (global.set $next (i32.add (local.get $ptr) (local.get $len)))
+    (if (i32.gt_u (global.get $next) (i32.mul (memory.size) (i32.const 65536)))
+      (then
+        (drop (memory.grow (i32.add (i32.div_u (local.get $len) (i32.const 65536)) (i32.const 1))))))
+    (local.get $ptr))
+
+  (func (export "run") (param $ptr i32) (param $len i32) (result i32)
+    (local $i i32)
+    (local $c i32)
+    (call $log (i32.const 0) (i32.const 22))
+    (block $done
+      (loop $next_byte
+        (br_if $done (i32.ge_u (local.get $i) (local.get $len)))
+        (local.set $c (i32.load8_u (i32.add (local.get $ptr) (local.get $i))))
+        (if (i32.and (i32.ge_u (local.get $c) (i32.const 97)) (i32.le_u (local.get $c) (i32.const 122)))
+          (then
+            (i32.store8 (i32.add (local.get $ptr) (local.get $i)) (i32.sub (local.get $c) (i32.const 32)))))
+        (local.set $i (i32.add (local.get $i) (i32.const 1)))
+        (br $next_byte)))
+    (call $output (local.get $ptr) (local.get $len))
+    (i32.const 0))
+)
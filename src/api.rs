@@ -0,0 +1,337 @@
+use serde::Deserialize;
+use serde_json::json;
+use serde_json::Value;
+use sqlx::PgPool;
+use sqlx::Postgres;
+use sqlx::Transaction;
+
+use crate::error::ErrorKind;
+use crate::error::JobError;
+use crate::patch;
+use crate::patch::JobPatch;
+use crate::patch::UpdateError;
+use crate::store::JobStore;
+use crate::store::PgJobStore;
+use crate::store::QueueShares;
+use crate::store::LEASE_SECS;
+use crate::usage;
+use crate::worker;
+use crate::worker::RetryPolicy;
+use crate::JobStatus;
+
+/// Lets workers written in other languages consume jobs over HTTP, with the
+/// same lease semantics as the Rust worker:
+///
+/// - `POST /jobs/reserve` claims due jobs and leases them for `LEASE_SECS`
+/// - `POST /jobs/<id>/extend` renews the lease of a job still being worked on
+/// - `POST /jobs/<id>/ack` marks a job done
+/// - `POST /jobs/<id>/nack` reports a failure, retried like a handler error
+/// - `PATCH /jobs/<id>` changes the priority, `run_at`, queue or payload of a
+///   job that is still queued
+/// - `GET /usage?hours=<n>&tenant=<name>` sums up the runtime spent per tenant
+///   and payload type, over the last 24 hours by default
+///
+/// Extend, ack and nack take the `attempt` the job was reserved with: once a lease
+/// expired and the job got reserved again, the late worker is turned down.
+///
+/// Requests changing jobs are turned down unless `authorized`, which is up to
+/// the server. Returns `None` for paths outside of the API.
+pub async fn route(
+    pg_pool: &PgPool,
+    method: &str,
+    path: &str,
+    body: &[u8],
+    authorized: bool,
+) -> Option<Response> {
+    let (path, query) = path.split_once('?').unwrap_or((path, ""));
+    let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+    let store = PgJobStore::new(pg_pool.clone());
+
+    if !authorized
+        && matches!(
+            (method, segments.as_slice()),
+            ("POST" | "PATCH", ["jobs", ..])
+        )
+    {
+        return Some((
+            "401 Unauthorized",
+            json!({ "error": "a valid bearer token is required" }),
+        ));
+    }
+
+    let result = match (method, segments.as_slice()) {
+        ("POST", ["jobs", "reserve"]) => reserve(&store, body).await,
+        ("POST", ["jobs", id, "extend"]) => extend(&store, pg_pool, id, body).await,
+        ("POST", ["jobs", id, "ack"]) => ack(&store, pg_pool, id, body).await,
+        ("POST", ["jobs", id, "nack"]) => nack(&store, pg_pool, id, body).await,
+        ("PATCH", ["jobs", id]) => update(pg_pool, id, body).await,
+        ("GET", ["usage"]) => usage(pg_pool, query).await,
+        (_, ["jobs", "reserve"] | ["jobs", _, "extend" | "ack" | "nack"]) => {
+            Err(method_not_allowed("POST"))
+        }
+        (_, ["jobs", _]) => Err(method_not_allowed("PATCH")),
+        (_, ["usage"]) => Err(method_not_allowed("GET")),
+        _ => return None,
+    };
+    Some(result.unwrap_or_else(|response| response))
+}
+
+/// An HTTP status, along with a JSON body.
+pub type Response = (&'static str, Value);
+
+fn internal_error(err: sqlx::Error) -> Response {
+    (
+        "500 Internal Server Error",
+        json!({ "error": err.to_string() }),
+    )
+}
+
+fn method_not_allowed(allowed: &str) -> Response {
+    (
+        "405 Method Not Allowed",
+        json!({ "error": format!("use {}", allowed) }),
+    )
+}
+
+fn parse<'a, T: Deserialize<'a>>(body: &'a [u8]) -> Result<T, Response> {
+    // An empty body stands for an empty object, so that defaults apply.
+    let body = if body.is_empty() { &b"{}"[..] } else { body };
+    serde_json::from_slice(body)
+        .map_err(|err| ("400 Bad Request", json!({ "error": err.to_string() })))
+}
+
+fn job_id(id: &str) -> Result<i64, Response> {
+    id.parse()
+        .map_err(|_| ("404 Not Found", json!({ "error": "no such job" })))
+}
+
+/// The value of `name` in a query string. Values aren't percent-decoded.
+fn query_param<'a>(query: &'a str, name: &str) -> Option<&'a str> {
+    query
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .find(|(key, _)| *key == name)
+        .map(|(_, value)| value)
+}
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct ReserveRequest {
+    batch_size: i64,
+    job_types: Vec<String>,
+    min_priority: Option<i32>,
+    queues: QueueShares,
+}
+
+impl Default for ReserveRequest {
+    fn default() -> Self {
+        ReserveRequest {
+            batch_size: 1,
+            job_types: vec![],
+            min_priority: None,
+            queues: QueueShares::default(),
+        }
+    }
+}
+
+async fn reserve(store: &PgJobStore, body: &[u8]) -> Result<Response, Response> {
+    let request: ReserveRequest = parse(body)?;
+    if request.batch_size < 0 {
+        return Err((
+            "400 Bad Request",
+            json!({ "error": "batch_size must not be negative" }),
+        ));
+    }
+    if let Err(err) = request.queues.check() {
+        return Err(("400 Bad Request", json!({ "error": err })));
+    }
+
+    // There may be no Rust worker around to requeue abandoned jobs.
+    store.reap().await.map_err(internal_error)?;
+    let jobs: Vec<Value> = store
+        .claim(
+            request.batch_size,
+            &request.job_types,
+            request.min_priority,
+            &request.queues,
+        )
+        .await
+        .map_err(internal_error)?
+        .into_iter()
+        .map(|job| {
+            json!({
+                "id": job.id,
+                "attempt": job.attempts,
+                "payload": job.payload.0,
+                "params": job.params.map(|params| params.0),
+                "metadata": job.metadata,
+                "correlation_id": job.correlation_id,
+                "checkpoint": job.checkpoint,
+                "lease_secs": LEASE_SECS,
+            })
+        })
+        .collect();
+    Ok(("200 OK", json!(jobs)))
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct LeaseRequest {
+    attempt: i32,
+}
+
+/// Turns down requests about a job not currently leased under `attempt`,
+/// returns how the job is retried otherwise.
+///
+/// The job's row stays locked until `tx` ends: the lease can't run out, be
+/// reaped or change hands between this check and the request's own update.
+async fn check_lease(
+    tx: &mut Transaction<'_, Postgres>,
+    id: i64,
+    attempt: i32,
+) -> Result<RetryPolicy, Response> {
+    let job = sqlx::query!(
+        r#"
+        SELECT status AS "status: JobStatus",
+               attempts,
+               COALESCE(locked_until > now(), false) AS "leased!",
+               max_attempts,
+               retry_backoff_secs
+        FROM jobs
+        WHERE id = $1
+        FOR UPDATE
+        "#,
+        id,
+    )
+    .fetch_optional(&mut *tx)
+    .await
+    .map_err(internal_error)?;
+
+    let job = job.ok_or(("404 Not Found", json!({ "error": "no such job" })))?;
+    let error = if job.status != JobStatus::Running || job.attempts != attempt {
+        "the job is not leased under this attempt"
+    } else if !job.leased {
+        "the lease of this attempt expired"
+    } else {
+        return Ok(RetryPolicy::new(job.max_attempts, job.retry_backoff_secs));
+    };
+    Err((
+        "409 Conflict",
+        json!({
+            "error": error,
+            "status": format!("{:?}", job.status),
+            "attempt": job.attempts,
+        }),
+    ))
+}
+
+async fn extend(
+    store: &PgJobStore,
+    pg_pool: &PgPool,
+    id: &str,
+    body: &[u8],
+) -> Result<Response, Response> {
+    let id = job_id(id)?;
+    let request: LeaseRequest = parse(body)?;
+    let mut tx = pg_pool.begin().await.map_err(internal_error)?;
+    check_lease(&mut tx, id, request.attempt).await?;
+
+    store.extend_in(&mut tx, id).await.map_err(internal_error)?;
+    tx.commit().await.map_err(internal_error)?;
+    Ok(("200 OK", json!({ "id": id, "lease_secs": LEASE_SECS })))
+}
+
+async fn ack(
+    store: &PgJobStore,
+    pg_pool: &PgPool,
+    id: &str,
+    body: &[u8],
+) -> Result<Response, Response> {
+    let id = job_id(id)?;
+    let request: LeaseRequest = parse(body)?;
+    let mut tx = pg_pool.begin().await.map_err(internal_error)?;
+    check_lease(&mut tx, id, request.attempt).await?;
+
+    store
+        .finish_in(&mut tx, id, request.attempt, JobStatus::Done)
+        .await
+        .map_err(internal_error)?;
+    tx.commit().await.map_err(internal_error)?;
+    Ok(("200 OK", json!({ "id": id, "status": "Done" })))
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct NackRequest {
+    attempt: i32,
+    error: String,
+    #[serde(default = "retryable")]
+    kind: ErrorKind,
+}
+
+fn retryable() -> ErrorKind {
+    ErrorKind::Retryable
+}
+
+async fn nack(
+    store: &PgJobStore,
+    pg_pool: &PgPool,
+    id: &str,
+    body: &[u8],
+) -> Result<Response, Response> {
+    let id = job_id(id)?;
+    let request: NackRequest = parse(body)?;
+    let mut tx = pg_pool.begin().await.map_err(internal_error)?;
+    let policy = check_lease(&mut tx, id, request.attempt).await?;
+
+    let error = JobError {
+        kind: request.kind,
+        message: request.error,
+    };
+    let retry_in = worker::retry_in(&error, request.attempt, &policy);
+    store
+        .fail_in(&mut tx, id, request.attempt, &error, retry_in)
+        .await
+        .map_err(internal_error)?;
+    tx.commit().await.map_err(internal_error)?;
+    let status = if retry_in.is_some() {
+        "Queued"
+    } else {
+        "Failed"
+    };
+    Ok((
+        "200 OK",
+        json!({ "id": id, "status": status, "retry_in": retry_in }),
+    ))
+}
+
+async fn update(pg_pool: &PgPool, id: &str, body: &[u8]) -> Result<Response, Response> {
+    let id = job_id(id)?;
+    let patch: JobPatch = parse(body)?;
+
+    match patch::update_job(pg_pool, id, &patch).await {
+        Ok(job) => Ok(("200 OK", json!(job))),
+        Err(UpdateError::Database(err)) => Err(internal_error(err)),
+        Err(err) => {
+            let status = match err {
+                UpdateError::NoSuchJob => "404 Not Found",
+                UpdateError::Invalid(_) => "400 Bad Request",
+                _ => "409 Conflict",
+            };
+            Err((status, json!({ "error": err.to_string() })))
+        }
+    }
+}
+
+async fn usage(pg_pool: &PgPool, query: &str) -> Result<Response, Response> {
+    let hours = match query_param(query, "hours") {
+        Some(hours) => hours
+            .parse()
+            .map_err(|_| ("400 Bad Request", json!({ "error": "invalid hours" })))?,
+        None => 24,
+    };
+    let usage = usage::fetch(pg_pool, hours, query_param(query, "tenant"))
+        .await
+        .map_err(internal_error)?;
+    Ok(("200 OK", json!(usage)))
+}
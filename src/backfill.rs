@@ -0,0 +1,243 @@
+use serde_json::json;
+use serde_json::Value;
+use sqlx::PgPool;
+use sqlx::Postgres;
+use sqlx::Transaction;
+
+/// Rewrites one payload into its new shape, `Ok(None)` meaning the payload
+/// doesn't need to change.
+pub type Transform = fn(&Value) -> Result<Option<Value>, String>;
+
+/// The transforms `backfill` can apply, by name. Transforms are compiled in:
+/// whenever the `Payload` enum changes shape, write a `Transform` in this
+/// module, add it here under a name, and rebuild before running
+/// `backfill <name>`.
+pub fn transform(name: &str) -> Option<Transform> {
+    match name {
+        "retag-payload" => Some(retag_payload),
+        _ => None,
+    }
+}
+
+/// Externally tagged payloads to internally tagged ones: the Rust side of the
+/// `retag_enum_json` SQL function, for databases where the retagging trigger
+/// was dropped.
+fn retag_payload(payload: &Value) -> Result<Option<Value>, String> {
+    match payload {
+        Value::String(variant) => Ok(Some(json!({ "type": variant }))),
+        Value::Object(object) if object.contains_key("type") => Ok(None),
+        Value::Object(object) if object.len() == 1 => match object.iter().next() {
+            Some((variant, Value::Object(fields))) => {
+                let mut retagged = fields.clone();
+                retagged.insert("type".to_string(), json!(variant));
+                Ok(Some(Value::Object(retagged)))
+            }
+            _ => Err(format!("unexpected variant content: {}", payload)),
+        },
+        _ => Err(format!("unexpected payload: {}", payload)),
+    }
+}
+
+/// Rewrites the payloads `transform` changes among `rows`, within `tx`.
+/// Returns how many changed, and the ids of the jobs it failed on.
+async fn apply(
+    tx: &mut Transaction<'_, Postgres>,
+    rows: &[(i64, Value)],
+    transform: Transform,
+) -> Result<(i64, Vec<i64>), sqlx::Error> {
+    let mut ids = vec![];
+    let mut payloads = vec![];
+    let mut failed = vec![];
+    for (id, payload) in rows {
+        match transform(payload) {
+            Ok(Some(payload)) => {
+                ids.push(*id);
+                payloads.push(payload);
+            }
+            Ok(None) => {}
+            Err(err) => {
+                println!("   job #{} left untouched: {}", id, err);
+                failed.push(*id);
+            }
+        }
+    }
+
+    sqlx::query!(
+        r#"
+        UPDATE jobs
+        SET payload = transformed.payload
+        FROM UNNEST($1::BIGINT[], $2::JSONB[]) AS transformed(id, payload)
+        WHERE jobs.id = transformed.id
+        "#,
+        &ids,
+        &payloads,
+    )
+    .execute(&mut *tx)
+    .await?;
+    Ok((ids.len() as i64, failed))
+}
+
+/// Applies `transform` to every job payload, one batch per transaction.
+///
+/// Progress is saved in the `backfills` table along with each batch, so an
+/// interrupted backfill resumes after the last committed batch when run again
+/// under the same name. So are the jobs the transform failed on: the next run
+/// tries them again first, and the backfill is only finished once it went
+/// through every job.
+pub async fn run(
+    pg_pool: &PgPool,
+    name: &str,
+    transform: Transform,
+    batch_size: i64,
+    restart: bool,
+) -> Result<(), sqlx::Error> {
+    if restart {
+        sqlx::query!("DELETE FROM backfills WHERE name = $1", name)
+            .execute(pg_pool)
+            .await?;
+    }
+
+    let progress = sqlx::query!(
+        r#"
+        INSERT INTO backfills (name)
+        VALUES ($1)
+        ON CONFLICT (name) DO UPDATE SET name = EXCLUDED.name
+        RETURNING last_id, updated_rows, failed_ids, finished_at IS NOT NULL AS "finished!"
+        "#,
+        name,
+    )
+    .fetch_one(pg_pool)
+    .await?;
+
+    if progress.finished {
+        println!(
+            "Backfill '{}' already finished, pass --restart to run it again",
+            name
+        );
+        return Ok(());
+    }
+
+    let mut updated = progress.updated_rows;
+    if !progress.failed_ids.is_empty() {
+        println!(
+            "Retrying the {} job(s) backfill '{}' failed on",
+            progress.failed_ids.len(),
+            name
+        );
+    }
+    for chunk in progress.failed_ids.chunks(batch_size.max(1) as usize) {
+        let mut tx = pg_pool.begin().await?;
+        let rows: Vec<(i64, Value)> = sqlx::query!(
+            "SELECT id, payload FROM jobs WHERE id = ANY($1) ORDER BY id FOR UPDATE",
+            chunk,
+        )
+        .fetch_all(&mut tx)
+        .await?
+        .into_iter()
+        .map(|row| (row.id, row.payload))
+        .collect();
+        let (changed, failed) = apply(&mut tx, &rows, transform).await?;
+
+        // Jobs deleted meanwhile are dropped from the list too.
+        sqlx::query!(
+            r#"
+            UPDATE backfills
+            SET failed_ids = ARRAY(SELECT id FROM UNNEST(failed_ids) AS id WHERE id <> ALL($1)) || $2::BIGINT[],
+                updated_rows = updated_rows + $3
+            WHERE name = $4
+            "#,
+            chunk,
+            &failed,
+            changed,
+            name,
+        )
+        .execute(&mut tx)
+        .await?;
+        tx.commit().await?;
+        updated += changed;
+    }
+
+    if progress.last_id > 0 {
+        println!(
+            "Resuming backfill '{}' after job #{}",
+            name, progress.last_id
+        );
+    }
+
+    let total = sqlx::query_scalar!(
+        r#"SELECT count(*) AS "count!" FROM jobs WHERE id > $1"#,
+        progress.last_id,
+    )
+    .fetch_one(pg_pool)
+    .await?;
+
+    let mut last_id = progress.last_id;
+    let mut scanned = 0;
+
+    loop {
+        let mut tx = pg_pool.begin().await?;
+
+        let rows: Vec<(i64, Value)> = sqlx::query!(
+            "SELECT id, payload FROM jobs WHERE id > $1 ORDER BY id LIMIT $2 FOR UPDATE",
+            last_id,
+            batch_size,
+        )
+        .fetch_all(&mut tx)
+        .await?
+        .into_iter()
+        .map(|row| (row.id, row.payload))
+        .collect();
+
+        let last_row = match rows.last() {
+            Some((id, _)) => *id,
+            None => break,
+        };
+        let (changed, failed) = apply(&mut tx, &rows, transform).await?;
+
+        sqlx::query!(
+            r#"
+            UPDATE backfills
+            SET last_id = $1, updated_rows = updated_rows + $2, failed_ids = failed_ids || $3::BIGINT[]
+            WHERE name = $4
+            "#,
+            last_row,
+            changed,
+            &failed,
+            name,
+        )
+        .execute(&mut tx)
+        .await?;
+
+        tx.commit().await?;
+
+        last_id = last_row;
+        updated += changed;
+        scanned += rows.len() as i64;
+        println!(
+            "{}/{} jobs scanned, {} updated so far",
+            scanned, total, updated
+        );
+    }
+
+    let failed = sqlx::query_scalar!(
+        r#"
+        UPDATE backfills
+        SET finished_at = CASE WHEN cardinality(failed_ids) = 0 THEN now() END
+        WHERE name = $1
+        RETURNING cardinality(failed_ids) AS "failed!"
+        "#,
+        name,
+    )
+    .fetch_one(pg_pool)
+    .await?;
+
+    if failed > 0 {
+        println!(
+            "Backfill '{}' not finished: {} jobs updated, {} failed, run it again to retry them",
+            name, updated, failed
+        );
+    } else {
+        println!("Backfill '{}' finished: {} jobs updated", name, updated);
+    }
+    Ok(())
+}
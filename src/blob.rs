@@ -0,0 +1,64 @@
+use std::fmt::Write;
+
+use serde::Deserialize;
+use serde::Serialize;
+use sha2::Digest;
+use sha2::Sha256;
+use sqlx::Executor;
+use sqlx::Postgres;
+
+/// A blob a job uses, e.g. a file to attach to an email, with the checksum
+/// of its content when the job was enqueued.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Attachment {
+    pub blob_id: i64,
+    pub filename: String,
+    /// Hex-encoded SHA-256.
+    pub sha256: String,
+}
+
+pub fn sha256(content: &[u8]) -> String {
+    Sha256::digest(content)
+        .iter()
+        .fold(String::with_capacity(64), |mut hex, byte| {
+            write!(hex, "{:02x}", byte).expect("writing to a String can't fail");
+            hex
+        })
+}
+
+/// Stores `content`, and returns how a job refers to it. `executor` should be
+/// the transaction enqueueing that job, lest a failed enqueue leaves the blob
+/// behind with nothing to refer to it.
+pub async fn put<'e, E>(
+    executor: E,
+    filename: &str,
+    content: &[u8],
+) -> Result<Attachment, sqlx::Error>
+where
+    E: Executor<'e, Database = Postgres>,
+{
+    let blob_id = sqlx::query_scalar!(
+        "INSERT INTO blobs (content) VALUES ($1) RETURNING id",
+        content
+    )
+    .fetch_one(executor)
+    .await?;
+
+    Ok(Attachment {
+        blob_id,
+        filename: filename.to_string(),
+        sha256: sha256(content),
+    })
+}
+
+/// Fails when `content` isn't what was recorded at enqueue time.
+pub fn verify(attachment: &Attachment, content: &[u8]) -> Result<(), String> {
+    let actual = sha256(content);
+    if actual != attachment.sha256 {
+        return Err(format!(
+            "checksum mismatch for {} (blob #{}): expected {}, got {}",
+            attachment.filename, attachment.blob_id, attachment.sha256, actual
+        ));
+    }
+    Ok(())
+}
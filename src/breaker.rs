@@ -0,0 +1,80 @@
+use sqlx::Postgres;
+use sqlx::Transaction;
+
+use crate::query_log::logged;
+
+/// Alerts are sent on this channel with `NOTIFY`, `sqlx-pb alerts` prints them.
+pub const ALERTS_CHANNEL: &str = "job_alerts";
+
+/// Only the jobs whose latest attempt started this recently are considered.
+const WINDOW_SECS: f64 = 60.0;
+
+/// Below that many jobs, a few failures say nothing about the job type.
+const MIN_JOBS: i64 = 10;
+
+/// The breaker opens once this share of the jobs considered failed.
+const FAILURE_RATE: f64 = 0.5;
+
+/// How long the job type isn't claimed for, once the breaker opened.
+const COOLDOWN_SECS: f64 = 60.0;
+
+/// Opens the circuit breaker of the job's type when too many of the jobs of
+/// that type attempted lately failed with a retryable error, so that a broken
+/// provider doesn't burn through all their retries at once. Permanent errors
+/// are about the job itself and don't count.
+///
+/// Once the cool-down is over, only the attempts made since then count: the
+/// breaker opens again as soon as enough of them failed.
+pub async fn on_job_failed(
+    tx: &mut Transaction<'_, Postgres>,
+    job_id: i64,
+) -> Result<(), sqlx::Error> {
+    // The error kinds listed are the ones `ErrorKind::is_retryable` accepts.
+    // The conflict clause leaves a breaker that is already open alone, only
+    // the worker that actually opened it sends the alert.
+    logged!(query!(
+        r#"
+        WITH recent AS (
+            SELECT jobs.job_type,
+                   count(*) AS jobs,
+                   count(*) FILTER (WHERE jobs.status <> 'Done' AND jobs.error_kind IN ('Retryable', 'Timeout')) AS failed
+            FROM jobs
+            LEFT JOIN circuit_breakers ON circuit_breakers.job_type = jobs.job_type
+            WHERE jobs.job_type = (SELECT job_type FROM jobs WHERE id = $1)
+              AND jobs.status <> 'Running'
+              AND jobs.started_at > GREATEST(now() - make_interval(secs => $2), circuit_breakers.open_until)
+            GROUP BY jobs.job_type
+        ), opened AS (
+            INSERT INTO circuit_breakers AS breaker (job_type, opened_at, open_until, failure_rate, jobs)
+            SELECT job_type, now(), now() + make_interval(secs => $5), failed::FLOAT8 / jobs, jobs
+            FROM recent
+            WHERE jobs >= $3
+              AND failed::FLOAT8 / jobs >= $4
+            ON CONFLICT (job_type) DO UPDATE
+            SET opened_at = excluded.opened_at,
+                open_until = excluded.open_until,
+                failure_rate = excluded.failure_rate,
+                jobs = excluded.jobs
+            WHERE breaker.open_until <= now()
+            RETURNING job_type, open_until, failure_rate, jobs
+        )
+        SELECT pg_notify($6, json_build_object(
+            'alert', 'circuit_open',
+            'job_type', job_type,
+            'open_until', open_until,
+            'failure_rate', failure_rate,
+            'jobs', jobs
+        )::TEXT)
+        FROM opened
+        "#,
+        job_id,
+        WINDOW_SECS,
+        MIN_JOBS,
+        FAILURE_RATE,
+        COOLDOWN_SECS,
+        ALERTS_CHANNEL,
+    ))
+    .run(|query| query.fetch_all(&mut *tx))
+    .await?;
+    Ok(())
+}
@@ -0,0 +1,1717 @@
+use std::collections::HashSet;
+use std::io::Write;
+use std::net::IpAddr;
+use std::net::Ipv4Addr;
+use std::str::FromStr;
+
+use serde::Serialize;
+use serde_json::json;
+use sqlx::postgres::PgArguments;
+use sqlx::postgres::PgListener;
+use sqlx::postgres::PgPoolOptions;
+use sqlx::query::QueryAs;
+use sqlx::types::Json;
+use sqlx::PgPool;
+use sqlx::Postgres;
+use sqlx::Transaction;
+
+use crate::backfill;
+use crate::blob;
+use crate::blob::Attachment;
+use crate::breaker;
+use crate::config;
+use crate::config::WorkerConfig;
+use crate::email;
+use crate::explain;
+use crate::federation::FederatedJobStore;
+use crate::inspect;
+use crate::inspect::Relative;
+use crate::maintenance;
+use crate::mapping;
+use crate::mapping::Derived;
+use crate::mapping::Manual;
+use crate::mapping::RowMapper;
+use crate::memory_store::MemoryJobStore;
+use crate::patch;
+use crate::patch::JobPatch;
+use crate::query_log;
+use crate::retention;
+use crate::schema;
+use crate::server;
+use crate::snapshot;
+use crate::stats;
+use crate::stats::Aggregate;
+use crate::stats::Dimension;
+use crate::store::JobStore;
+use crate::store::PgJobStore;
+use crate::store::TxJobStore;
+use crate::usage;
+use crate::worker;
+use crate::workflow;
+use crate::workflow::Compensation;
+use crate::workflow::Step;
+use crate::workflow::WorkflowStatus;
+use crate::JobRow;
+use crate::JobStatus;
+use crate::Params;
+use crate::Payload;
+
+pub async fn run(pg_pool: &PgPool, args: &[String]) {
+    let (command, rest) = args.split_first().expect("no command given");
+    match command.as_str() {
+        "enqueue" => enqueue(pg_pool, rest).await,
+        "list" => list(pg_pool, rest).await,
+        "work" => work(pg_pool, rest).await,
+        "tree" => tree(pg_pool, rest).await,
+        "inspect" => inspect_job(pg_pool, rest).await,
+        "retry" => retry(pg_pool, rest).await,
+        "update" => update(pg_pool, rest).await,
+        "keep" => keep(pg_pool, rest).await,
+        "maintain" => maintain(pg_pool, rest).await,
+        "drain" => drain(pg_pool, rest).await,
+        "queue" => queue_settings(pg_pool, rest).await,
+        "payload-format" => payload_format(pg_pool, rest).await,
+        "window" => match rest.split_first() {
+            Some((subcommand, rest)) if subcommand == "add" => add_window(pg_pool, rest).await,
+            Some((subcommand, _)) if subcommand == "list" => list_windows(pg_pool).await,
+            Some((subcommand, rest)) if subcommand == "remove" => {
+                remove_window(pg_pool, rest).await
+            }
+            _ => usage("Unknown window command"),
+        },
+        "scheduler" => scheduler(pg_pool, rest).await,
+        "simulate" => simulate().await,
+        "bench-mapping" => bench_mapping(pg_pool, rest).await,
+        "explain" => explain_queries(pg_pool, rest).await,
+        "backfill" => run_backfill(pg_pool, rest).await,
+        "snapshot" => take_snapshot(pg_pool, rest).await,
+        "restore" => restore_snapshot(pg_pool, rest).await,
+        "stats" => show_stats(pg_pool, rest).await,
+        "serve" => serve(pg_pool, rest).await,
+        "alerts" => alerts(pg_pool).await,
+        "usage" => show_usage(pg_pool, rest).await,
+        "workflow" => match rest.split_first() {
+            Some((subcommand, rest)) if subcommand == "start" => {
+                start_workflow(pg_pool, rest).await
+            }
+            Some((subcommand, rest)) if subcommand == "show" => show_workflow(pg_pool, rest).await,
+            _ => usage("Unknown workflow command"),
+        },
+        other => usage(&format!("Unknown command: {}", other)),
+    }
+}
+
+/// Operational data recorded alongside the jobs enqueued from the command line.
+#[derive(Serialize)]
+struct CliMetadata {
+    enqueuer: &'static str,
+}
+
+fn usage(reason: &str) -> ! {
+    eprintln!("{}", reason);
+    eprintln!();
+    eprintln!("Usage:");
+    eprintln!("  sqlx-pb                                           run the query demo");
+    eprintln!("  sqlx-pb enqueue [--email <address> [--follow-up]]... [--template <name> [--var <name=value>]...] [--attach <file>]... [--tag <tag>]... [--correlation-id <id>] [--queue <name>] [--concurrency-key <key>] [--tenant <name>] [--priority <n>] [--max-attempts <n>] [--retry-backoff <seconds>] [--timeout <seconds>]");
+    eprintln!("  sqlx-pb enqueue --stdin [--follow-up] [--tag <tag>]... [--correlation-id <id>] [--queue <name>] [--concurrency-key <key>] [--tenant <name>] [--priority <n>] [--max-attempts <n>] [--retry-backoff <seconds>] [--timeout <seconds>]");
+    eprintln!("  sqlx-pb list [--tag <tag>]... [--correlation-id <id>] [--type <payload type>]");
+    eprintln!(
+        "  sqlx-pb work [--batch-size <n>] [--poll <seconds>] [--type <payload type>]... [--min-priority <n>] [--source <name=url>]... [--transactional] [--log-queries] [--check-claims]"
+    );
+    eprintln!(
+        "  sqlx-pb work --config <file.json> [--source <name=url>]... [--transactional] [--log-queries] [--check-claims]"
+    );
+    eprintln!("  sqlx-pb simulate                                  run the worker on sample jobs, in memory");
+    eprintln!("  sqlx-pb bench-mapping [--rows <n>] [--iterations <n>]");
+    eprintln!("  sqlx-pb explain [--batch-size <n>] [--tag <tag>]... [--correlation-id <id>] [--type <payload type>]");
+    eprintln!("  sqlx-pb tree <job_id>");
+    eprintln!("  sqlx-pb inspect <job_id> [--json]");
+    eprintln!("  sqlx-pb retry <job_id>");
+    eprintln!("  sqlx-pb update <job_id> [--priority <n>] [--run-at <timestamp>] [--queue <name>] [--payload <json>]");
+    eprintln!("  sqlx-pb keep <job_id> [--release]");
+    eprintln!("  sqlx-pb maintain [--retention-days <n>] [--archive]");
+    eprintln!("  sqlx-pb drain <queue> [--timeout <seconds>]");
+    eprintln!("  sqlx-pb drain <queue> --cancel");
+    eprintln!("  sqlx-pb queue <queue> [--reset] [--tenant <name>] [--priority <n>] [--max-attempts <n>] [--retry-backoff <seconds>] [--timeout <seconds>]");
+    eprintln!("  sqlx-pb payload-format [<payload type> <internal|adjacent>]");
+    eprintln!("  sqlx-pb window add <queue> --cron <expression> --duration <seconds>");
+    eprintln!("  sqlx-pb window list");
+    eprintln!("  sqlx-pb window remove <window_id>");
+    eprintln!(
+        "  sqlx-pb scheduler [--interval <seconds>] [--retention-days <n>] [--archive]   open and close maintenance windows, expire failed jobs"
+    );
+    eprintln!("  sqlx-pb backfill <transform> [--batch-size <n>] [--restart]");
+    eprintln!("  sqlx-pb snapshot <file>");
+    eprintln!("  sqlx-pb restore <file>");
+    eprintln!("  sqlx-pb stats [--once] [--json] [--interval <seconds>]");
+    eprintln!("  sqlx-pb stats --format csv [--group-by <day,queue,status,type>]");
+    eprintln!("  sqlx-pb serve [--bind <address>] [--port <port>]");
+    eprintln!("  sqlx-pb usage [--hours <n>] [--tenant <name>] [--json]");
+    eprintln!(
+        "  sqlx-pb alerts                                    print alerts as they are raised"
+    );
+    eprintln!("  sqlx-pb workflow start --email <address> [--wait <seconds>]");
+    eprintln!("  sqlx-pb workflow show <workflow_id>");
+    std::process::exit(1)
+}
+
+/// Collects every value given to a repeatable option, i.e. `--tag a --tag b`.
+fn option_values<'a>(args: &'a [String], name: &str) -> Vec<&'a str> {
+    args.windows(2)
+        .filter(|pair| pair[0] == name)
+        .map(|pair| pair[1].as_str())
+        .collect()
+}
+
+fn option_value<'a>(args: &'a [String], name: &str) -> Option<&'a str> {
+    option_values(args, name).pop()
+}
+
+/// The value of a numeric option, which must satisfy `valid`.
+fn number_option<T: FromStr>(args: &[String], name: &str, valid: impl Fn(&T) -> bool) -> Option<T> {
+    option_value(args, name).map(|value| {
+        value
+            .parse()
+            .ok()
+            .filter(|number| valid(number))
+            .unwrap_or_else(|| {
+                usage(&format!(
+                    "Invalid {}: {}",
+                    name.trim_start_matches("--"),
+                    value
+                ))
+            })
+    })
+}
+
+fn job_id(args: &[String]) -> i64 {
+    let id = args.first().unwrap_or_else(|| usage("Missing job id"));
+    id.parse()
+        .unwrap_or_else(|_| usage(&format!("Invalid job id: {}", id)))
+}
+
+fn workflow_id(args: &[String]) -> i64 {
+    let id = args.first().unwrap_or_else(|| usage("Missing workflow id"));
+    id.parse()
+        .unwrap_or_else(|_| usage(&format!("Invalid workflow id: {}", id)))
+}
+
+fn has_flag(args: &[String], name: &str) -> bool {
+    args.iter().any(|arg| arg == name)
+}
+
+fn tags(args: &[String]) -> Vec<String> {
+    option_values(args, "--tag")
+        .into_iter()
+        .map(String::from)
+        .collect()
+}
+
+/// What `enqueue` and `queue` are given for the jobs, `None` where an option
+/// is left out.
+struct JobOptions<'a> {
+    tenant: Option<&'a str>,
+    priority: Option<i32>,
+    max_attempts: Option<i32>,
+    retry_backoff_secs: Option<f64>,
+    timeout_secs: Option<f64>,
+}
+
+impl<'a> JobOptions<'a> {
+    fn from_args(args: &'a [String]) -> Self {
+        JobOptions {
+            tenant: option_value(args, "--tenant"),
+            priority: number_option(args, "--priority", |_| true),
+            max_attempts: number_option(args, "--max-attempts", |attempts| *attempts > 0),
+            retry_backoff_secs: number_option(args, "--retry-backoff", |secs: &f64| *secs >= 0.0),
+            timeout_secs: number_option(args, "--timeout", |secs: &f64| *secs > 0.0),
+        }
+    }
+}
+
+async fn enqueue(pg_pool: &PgPool, args: &[String]) {
+    let stdin = has_flag(args, "--stdin").then(read_payloads);
+    // The attachments and their job are committed together.
+    let mut tx = pg_pool
+        .begin()
+        .await
+        .expect("Could not start a transaction");
+    let payloads = if let Some(payloads) = stdin {
+        payloads
+    } else {
+        let mut emails: Vec<String> = option_values(args, "--email")
+            .into_iter()
+            .map(String::from)
+            .collect();
+        let template = template(args);
+        let files = option_values(args, "--attach");
+        let single = template.is_some() || !files.is_empty();
+        let payload = match emails.len() {
+            0 if single => usage("--template and --attach need an --email"),
+            0 => Payload::NOOP,
+            1 => Payload::SendEmail {
+                email: emails.remove(0),
+                template,
+                attachments: attach(&mut tx, &files).await,
+            },
+            _ if single => usage("--template and --attach only apply to a single --email"),
+            _ => Payload::SendEmailBatch { emails },
+        };
+        vec![payload]
+    };
+    let params = has_flag(args, "--follow-up").then_some(Params::FollowUp(true));
+    let options = JobOptions::from_args(args);
+
+    // What isn't given falls back to the queue's defaults, then to the
+    // columns' own.
+    let ids = sqlx::query_scalar!(
+        r#"
+        INSERT INTO jobs (status, payload, params, tags, metadata, correlation_id, queue, concurrency_key, tenant, priority, max_attempts, retry_backoff_secs, timeout_secs)
+        SELECT $1, input.payload, $3, $4, $5, $6, $7, $8, COALESCE($9, settings.tenant, 'default'), COALESCE($10, settings.priority, 0),
+               COALESCE($11, settings.max_attempts), COALESCE($12, settings.retry_backoff_secs), COALESCE($13, settings.timeout_secs)
+        FROM UNNEST($2::JSONB[]) WITH ORDINALITY AS input(payload, position)
+        LEFT JOIN queue_settings settings ON settings.queue = $7
+        ORDER BY input.position
+        RETURNING id
+        "#,
+        JobStatus::Queued as JobStatus,
+        &payloads
+            .iter()
+            .map(|payload| json!(payload))
+            .collect::<Vec<_>>(),
+        params.map(|params| json!(params)),
+        &tags(args),
+        json!(CliMetadata {
+            enqueuer: "sqlx-pb cli"
+        }),
+        option_value(args, "--correlation-id"),
+        option_value(args, "--queue").unwrap_or("default"),
+        option_value(args, "--concurrency-key"),
+        options.tenant,
+        options.priority,
+        options.max_attempts,
+        options.retry_backoff_secs,
+        options.timeout_secs,
+    )
+    .fetch_all(&mut *tx)
+    .await
+    .expect("Could not enqueue job");
+    tx.commit().await.expect("Could not enqueue job");
+
+    match ids.as_slice() {
+        [] => println!("Nothing to enqueue"),
+        [id] => println!("Enqueued job #{}", id),
+        [first, .., last] => println!("Enqueued {} jobs, #{} to #{}", ids.len(), first, last),
+    }
+}
+
+/// The template given with `--template`, along with its `--var name=value`.
+fn template(args: &[String]) -> Option<email::Template> {
+    let name = option_value(args, "--template")?;
+    let variables = option_values(args, "--var")
+        .into_iter()
+        .map(|var| match var.split_once('=') {
+            Some((name, value)) => (name.to_string(), json!(value)),
+            None => usage(&format!("Invalid variable, expected name=value: {}", var)),
+        })
+        .collect();
+    Some(email::Template {
+        name: name.to_string(),
+        variables,
+    })
+}
+
+/// Stores each file as a blob, along with its checksum.
+async fn attach(tx: &mut Transaction<'_, Postgres>, paths: &[&str]) -> Vec<Attachment> {
+    let mut attachments = vec![];
+    for path in paths {
+        let content = std::fs::read(path)
+            .unwrap_or_else(|err| usage(&format!("Cannot read {}: {}", path, err)));
+        let filename = std::path::Path::new(path)
+            .file_name()
+            .map_or(path.to_string(), |name| name.to_string_lossy().into_owned());
+        let attachment = blob::put(&mut **tx, &filename, &content)
+            .await
+            .expect("Could not store the attachment");
+        attachments.push(attachment);
+    }
+    attachments
+}
+
+/// Reads one JSON payload per line from stdin, blank lines aside. Every line
+/// is checked before anything gets enqueued: on any invalid line, the errors
+/// are reported and nothing is, so the fixed input can be fed again as is.
+fn read_payloads() -> Vec<Payload> {
+    let mut payloads = vec![];
+    let mut errors = 0;
+    for (index, line) in std::io::stdin().lines().enumerate() {
+        let line = line.expect("Could not read stdin");
+        if line.trim().is_empty() {
+            continue;
+        }
+        match serde_json::from_str(&line) {
+            Ok(payload) => payloads.push(payload),
+            Err(err) => {
+                eprintln!("line {}: {}", index + 1, err);
+                errors += 1;
+            }
+        }
+    }
+
+    if errors > 0 {
+        eprintln!("{} invalid line(s), nothing enqueued", errors);
+        std::process::exit(1);
+    }
+    payloads
+}
+
+/// The filters of `list`.
+#[derive(Default)]
+struct Search {
+    tags: Vec<String>,
+    correlation_id: Option<String>,
+    job_type: Option<String>,
+}
+
+impl Search {
+    fn from_args(args: &[String]) -> Self {
+        Search {
+            tags: tags(args),
+            correlation_id: option_value(args, "--correlation-id").map(String::from),
+            job_type: option_value(args, "--type").map(String::from),
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.tags.is_empty() && self.correlation_id.is_none() && self.job_type.is_none()
+    }
+
+    // Only the filters given make it to the query, so that the planner picks
+    // the matching index (the GIN one for tags). Each combination being its
+    // own statement, they aren't kept prepared: that would only crowd the
+    // static queries out of the connection's statement cache.
+    fn sql(&self) -> String {
+        let mut conditions = vec![];
+        if !self.tags.is_empty() {
+            conditions.push(format!("tags @> ${}", conditions.len() + 1));
+        }
+        if self.correlation_id.is_some() {
+            conditions.push(format!("correlation_id = ${}", conditions.len() + 1));
+        }
+        if self.job_type.is_some() {
+            conditions.push(format!("job_type = ${}", conditions.len() + 1));
+        }
+        let filter = if conditions.is_empty() {
+            String::new()
+        } else {
+            format!("WHERE {}", conditions.join(" AND "))
+        };
+        format!(
+            "SELECT {} FROM jobs {} ORDER BY id",
+            mapping::JOB_COLUMNS,
+            filter
+        )
+    }
+
+    /// The query for `sql`, with the filters bound.
+    fn query<'q>(&'q self, sql: &'q str) -> QueryAs<'q, Postgres, JobRow, PgArguments> {
+        let mut query = sqlx::query_as::<_, JobRow>(sql).persistent(false);
+        if !self.tags.is_empty() {
+            query = query.bind(&self.tags);
+        }
+        if let Some(correlation_id) = &self.correlation_id {
+            query = query.bind(correlation_id);
+        }
+        if let Some(job_type) = &self.job_type {
+            query = query.bind(job_type);
+        }
+        query
+    }
+}
+
+async fn list(pg_pool: &PgPool, args: &[String]) {
+    let search = Search::from_args(args);
+    let sql = search.sql();
+    let jobs = search
+        .query(&sql)
+        .fetch_all(pg_pool)
+        .await
+        .expect("failed to list jobs!");
+
+    for job in jobs {
+        println!(
+            "#{} ({:?}) -> {:?} | {:?} | tags: {:?} | metadata: {} | correlation_id: {:?} | parent: {:?}",
+            job.id,
+            job.status,
+            job.payload.0,
+            job.params,
+            job.tags,
+            job.metadata,
+            job.correlation_id,
+            job.parent_job_id
+        );
+    }
+}
+
+async fn work(pg_pool: &PgPool, args: &[String]) {
+    let config = match option_value(args, "--config") {
+        Some(path) => {
+            let path = std::path::PathBuf::from(path);
+            let config = config::load(&path).unwrap_or_else(|err| usage(&err));
+            config::watch(path, config)
+        }
+        None => {
+            let mut config = WorkerConfig::default();
+            if let Some(batch_size) = option_value(args, "--batch-size") {
+                config.batch_size = batch_size
+                    .parse()
+                    .unwrap_or_else(|_| usage(&format!("Invalid batch size: {}", batch_size)));
+            }
+            config.job_types = option_values(args, "--type")
+                .into_iter()
+                .map(String::from)
+                .collect();
+            config.check().unwrap_or_else(|err| usage(&err));
+            if let Some(min_priority) = option_value(args, "--min-priority") {
+                config.min_priority = Some(min_priority.parse().unwrap_or_else(|_| {
+                    usage(&format!("Invalid minimum priority: {}", min_priority))
+                }));
+            }
+            if let Some(poll) = option_value(args, "--poll") {
+                config.poll_secs = Some(
+                    poll.parse()
+                        .unwrap_or_else(|_| usage(&format!("Invalid poll interval: {}", poll))),
+                );
+            }
+            // Nothing to reload from: the sender can go right away.
+            tokio::sync::watch::channel(config).1
+        }
+    };
+    if has_flag(args, "--log-queries") {
+        query_log::enable();
+    }
+    let pg_store =
+        |pg_pool: PgPool| PgJobStore::new(pg_pool).check_claims(has_flag(args, "--check-claims"));
+    let transactional = has_flag(args, "--transactional");
+
+    let sources = sources(args).await;
+    if sources.is_empty() {
+        let store = pg_store(pg_pool.clone());
+        if transactional {
+            worker::run(&TxJobStore::new(&store), config).await;
+        } else {
+            worker::run(&store, config).await;
+        }
+        return;
+    }
+
+    let stores: Vec<(String, PgJobStore)> = sources
+        .into_iter()
+        .map(|(name, pg_pool)| (name, pg_store(pg_pool)))
+        .collect();
+    if transactional {
+        let store = FederatedJobStore::new(
+            stores
+                .iter()
+                .map(|(name, store)| (name.clone(), TxJobStore::new(store)))
+                .collect(),
+        );
+        worker::run(&store, config).await;
+    } else {
+        worker::run(&FederatedJobStore::new(stores), config).await;
+    }
+}
+
+/// How long connecting to a source may take: a source that is down must not
+/// hold up the others for long.
+const SOURCE_CONNECT_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(2);
+
+/// The databases given with `--source <name>=<url>`, checked for schema drift
+/// when reachable. They are connected to lazily: a source that is down when
+/// the worker starts gets retried like one that goes down later.
+async fn sources(args: &[String]) -> Vec<(String, PgPool)> {
+    let mut sources: Vec<(String, PgPool)> = vec![];
+    for source in option_values(args, "--source") {
+        let (name, url) = source.split_once('=').unwrap_or_else(|| {
+            usage(&format!(
+                "Invalid source, expected <name>=<url>: {}",
+                source
+            ))
+        });
+        if sources.iter().any(|(known, _)| known == name) {
+            usage(&format!("Duplicate source: {}", name));
+        }
+        let options = crate::connect_options(url)
+            .unwrap_or_else(|err| usage(&format!("Invalid URL for source {}: {}", name, err)));
+        let pg_pool = PgPoolOptions::new()
+            .connect_timeout(SOURCE_CONNECT_TIMEOUT)
+            .connect_lazy_with(options);
+
+        match schema::drift(&pg_pool).await {
+            Ok(drift) if drift.is_empty() => {}
+            Ok(drift) => {
+                eprintln!(
+                    "The schema of source {} doesn't match what this binary expects:",
+                    name
+                );
+                schema::report(&drift);
+                std::process::exit(1);
+            }
+            Err(err) => println!("Source {} is unreachable for now ({})", name, err),
+        }
+        sources.push((name.to_string(), pg_pool));
+    }
+    sources
+}
+
+/// Runs the worker against a `MemoryJobStore`: handlers and the worker loop
+/// can be played with without touching the database.
+async fn simulate() {
+    let store = MemoryJobStore::default();
+    store.enqueue(
+        Payload::SendEmail {
+            email: "user@example.com".to_string(),
+            template: None,
+            attachments: vec![],
+        },
+        Some(Params::FollowUp(true)),
+    );
+    store.enqueue(
+        Payload::SendEmail {
+            email: "ann@example.com".to_string(),
+            template: Some(email::Template {
+                name: "welcome".to_string(),
+                variables: [("name".to_string(), json!("Ann"))].into_iter().collect(),
+            }),
+            attachments: vec![store.put_blob("terms.txt", b"Be nice.\n")],
+        },
+        None,
+    );
+    store.enqueue(Payload::NOOP, None);
+    store.enqueue(
+        Payload::SendEmailBatch {
+            emails: (1..=250)
+                .map(|n| format!("user{}@example.com", n))
+                .collect(),
+        },
+        None,
+    );
+
+    worker::run(
+        &store,
+        tokio::sync::watch::channel(WorkerConfig::default()).1,
+    )
+    .await;
+
+    println!();
+    for job in store.rows() {
+        println!(
+            "#{} ({:?}) -> {:?} | attempts: {} | parent: {:?} | checkpoint: {:?}",
+            job.id, job.status, job.payload.0, job.attempts, job.parent_job_id, job.checkpoint
+        );
+    }
+}
+
+/// Compares the decoding of `PgRow`s into `JobRow`s, derived vs hand-written.
+/// Only decoding is timed, the rows are fetched beforehand.
+async fn bench_mapping(pg_pool: &PgPool, args: &[String]) {
+    let count = match option_value(args, "--rows") {
+        Some(count) => count
+            .parse()
+            .unwrap_or_else(|_| usage(&format!("Invalid number of rows: {}", count))),
+        None => 10_000,
+    };
+    let iterations = match option_value(args, "--iterations") {
+        Some(iterations) => iterations
+            .parse()
+            .ok()
+            .filter(|iterations| *iterations > 0)
+            .unwrap_or_else(|| usage(&format!("Invalid number of iterations: {}", iterations))),
+        None => 10,
+    };
+
+    let rows = mapping::sample_rows(pg_pool, count)
+        .await
+        .expect("failed to generate rows!");
+    if let Err(err) = mapping::compare(&rows) {
+        eprintln!("The mappers disagree, {}", err);
+        std::process::exit(1);
+    }
+    println!(
+        "Both mappers agree on {} rows, average over {} iterations:",
+        rows.len(),
+        iterations
+    );
+
+    let derived = mapping::time::<Derived>(&rows, iterations);
+    let manual = mapping::time::<Manual>(&rows, iterations);
+    for (name, elapsed) in [(Derived::NAME, derived), (Manual::NAME, manual)] {
+        println!(
+            "  {:<8} {:>10.3?} {:>8.0}ns/row",
+            name,
+            elapsed,
+            elapsed.as_nanos() as f64 / rows.len().max(1) as f64
+        );
+    }
+    println!(
+        "  {} takes {:.2}x the time of {}",
+        Manual::NAME,
+        manual.as_secs_f64() / derived.as_secs_f64(),
+        Derived::NAME
+    );
+}
+
+/// Analyzes the queries the queue relies on against the current data, for
+/// plans that won't hold up as tables grow. The claim is analyzed as a
+/// worker claiming every payload type runs it.
+async fn explain_queries(pg_pool: &PgPool, args: &[String]) {
+    let batch_size = match option_value(args, "--batch-size") {
+        Some(batch_size) => batch_size
+            .parse()
+            .unwrap_or_else(|_| usage(&format!("Invalid batch size: {}", batch_size))),
+        None => WorkerConfig::default().batch_size,
+    };
+    let search = Search::from_args(args);
+    let searches = if search.is_empty() {
+        sample_searches(pg_pool).await
+    } else {
+        vec![search]
+    };
+
+    let mut analyses = vec![];
+    let analysis = explain::analyze(
+        pg_pool,
+        PgJobStore::claim_query(batch_size, &[], None, &json!({}), &json!({})).query,
+    )
+    .await;
+    analyses.push((format!("claim, batch of {}", batch_size), analysis));
+    let analysis = explain::analyze(pg_pool, stats::counts_query()).await;
+    analyses.push(("stats, counts".to_string(), analysis));
+    let analysis = explain::analyze(pg_pool, stats::by_type_query()).await;
+    analyses.push(("stats, by type".to_string(), analysis));
+    for search in &searches {
+        let sql = search.sql();
+        let analysis = explain::analyze(pg_pool, search.query(&sql)).await;
+        let mut filters: Vec<String> = search
+            .tags
+            .iter()
+            .map(|tag| format!("--tag {}", tag))
+            .collect();
+        filters.extend(
+            search
+                .correlation_id
+                .iter()
+                .map(|id| format!("--correlation-id {}", id)),
+        );
+        filters.extend(
+            search
+                .job_type
+                .iter()
+                .map(|job_type| format!("--type {}", job_type)),
+        );
+        analyses.push((format!("list {}", filters.join(" ")), analysis));
+    }
+
+    let mut warnings = 0;
+    for (name, analysis) in analyses {
+        let analysis = analysis.unwrap_or_else(|err| panic!("failed to explain {}: {}", name, err));
+        println!("{}", name);
+        for line in &analysis.plan {
+            println!("  {}", line);
+        }
+        println!(
+            "  planning {:.3} ms, execution {:.3} ms",
+            analysis.planning_ms, analysis.execution_ms
+        );
+        for warning in &analysis.warnings {
+            println!("  warning: {}", warning);
+        }
+        println!();
+        warnings += analysis.warnings.len();
+    }
+    println!("{} warning(s)", warnings);
+}
+
+/// Each filter of `list` on its own, with the value of the latest job that
+/// has one, for `explain` to try when given none.
+async fn sample_searches(pg_pool: &PgPool) -> Vec<Search> {
+    let sample = sqlx::query!(
+        r#"
+        SELECT (SELECT tags[1] FROM jobs WHERE cardinality(tags) > 0 ORDER BY id DESC LIMIT 1) AS tag,
+               (SELECT correlation_id FROM jobs WHERE correlation_id IS NOT NULL ORDER BY id DESC LIMIT 1) AS correlation_id,
+               (SELECT job_type FROM jobs WHERE job_type IS NOT NULL ORDER BY id DESC LIMIT 1) AS job_type
+        "#
+    )
+    .fetch_one(pg_pool)
+    .await
+    .expect("failed to sample the jobs!");
+
+    let mut searches = vec![];
+    if let Some(tag) = sample.tag {
+        searches.push(Search {
+            tags: vec![tag],
+            ..Search::default()
+        });
+    }
+    if let Some(correlation_id) = sample.correlation_id {
+        searches.push(Search {
+            correlation_id: Some(correlation_id),
+            ..Search::default()
+        });
+    }
+    if let Some(job_type) = sample.job_type {
+        searches.push(Search {
+            job_type: Some(job_type),
+            ..Search::default()
+        });
+    }
+    searches
+}
+
+/// Puts a failed (or stuck) job back in the queue, it will resume from its
+/// latest checkpoint. A running job is only stuck once its lease expired, or
+/// when it was never leased: otherwise its worker may still be at it, and the
+/// job would run twice.
+///
+/// The previous outcome is cleared, so that the job doesn't show as finished.
+async fn retry(pg_pool: &PgPool, args: &[String]) {
+    let result = sqlx::query!(
+        r#"
+        UPDATE jobs
+        SET status = $1, locked_until = NULL, finished_at = NULL, last_error = NULL, error_kind = NULL
+        WHERE id = $2
+          AND (status = 'Failed' OR (status = 'Running' AND COALESCE(locked_until < now(), true)))
+        "#,
+        JobStatus::Queued as JobStatus,
+        job_id(args),
+    )
+    .execute(pg_pool)
+    .await
+    .expect("failed to retry job!");
+
+    if result.rows_affected() == 0 {
+        usage(&format!(
+            "No failed or stuck job: {} (a running job is stuck once its lease expired)",
+            job_id(args)
+        ));
+    }
+    println!("Requeued job #{}", job_id(args));
+}
+
+/// Fixes a job before it runs, e.g. a typo in its email address, instead of
+/// enqueueing it again.
+async fn update(pg_pool: &PgPool, args: &[String]) {
+    let patch = JobPatch {
+        priority: number_option(args, "--priority", |_| true),
+        run_at: option_value(args, "--run-at").map(String::from),
+        queue: option_value(args, "--queue").map(String::from),
+        payload: option_value(args, "--payload").map(|payload| {
+            serde_json::from_str(payload)
+                .unwrap_or_else(|err| usage(&format!("Invalid payload: {}", err)))
+        }),
+    };
+    if patch.is_empty() {
+        usage("Nothing to update");
+    }
+
+    match patch::update_job(pg_pool, job_id(args), &patch).await {
+        Ok(job) => println!(
+            "Updated job #{}: queue '{}', priority {}, due at {} -> {}",
+            job.id, job.queue, job.priority, job.run_at, job.payload
+        ),
+        Err(err) => {
+            eprintln!("Cannot update job #{}: {}", job_id(args), err);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Exempts a job from expiry, e.g. while it is being investigated.
+async fn keep(pg_pool: &PgPool, args: &[String]) {
+    let release = has_flag(args, "--release");
+    let found = retention::keep(pg_pool, job_id(args), !release)
+        .await
+        .expect("failed to update the job!");
+
+    if !found {
+        usage(&format!("No such job: {}", job_id(args)));
+    }
+    if release {
+        println!("Job #{} expires again", job_id(args));
+    } else {
+        println!("Job #{} is kept until released", job_id(args));
+    }
+}
+
+/// The periodic housekeeping: requeues abandoned jobs, in case no worker is
+/// running to do it, moves maintenance windows on in case no scheduler is,
+/// then expires the jobs that failed for good.
+async fn maintain(pg_pool: &PgPool, args: &[String]) {
+    let (retention_days, archive) = retention_options(args);
+
+    let reaped = PgJobStore::new(pg_pool.clone())
+        .reap()
+        .await
+        .expect("failed to reap jobs!");
+    println!("Reaped {} abandoned job(s)", reaped);
+
+    let advanced = maintenance::advance(pg_pool)
+        .await
+        .expect("failed to advance windows!");
+    println!(
+        "Moved {} maintenance window(s) on to their next occurrence",
+        advanced.len()
+    );
+
+    expire_dead_jobs(pg_pool, retention_days, archive).await;
+}
+
+/// `--retention-days` and `--archive`, as `maintain` and `scheduler` take them.
+fn retention_options(args: &[String]) -> (i32, bool) {
+    let retention_days = match option_value(args, "--retention-days") {
+        Some(days) => days
+            .parse()
+            .ok()
+            .filter(|days| *days >= 0)
+            .unwrap_or_else(|| usage(&format!("Invalid retention: {}", days))),
+        None => retention::DEFAULT_RETENTION_DAYS,
+    };
+    (retention_days, has_flag(args, "--archive"))
+}
+
+async fn expire_dead_jobs(pg_pool: &PgPool, retention_days: i32, archive: bool) {
+    let expired = retention::expire_dead_jobs(pg_pool, retention_days, archive)
+        .await
+        .expect("failed to expire jobs!");
+    println!(
+        "{} {} job(s) failed more than {} day(s) ago",
+        if archive { "Archived" } else { "Deleted" },
+        expired,
+        retention_days
+    );
+}
+
+/// Sets the defaults of the jobs enqueued in a queue, leaving those not given
+/// as they were, or clearing them all first with `--reset`. Only later
+/// enqueues see them.
+async fn queue_settings(pg_pool: &PgPool, args: &[String]) {
+    let queue = args
+        .first()
+        .filter(|arg| !arg.starts_with("--"))
+        .unwrap_or_else(|| usage("Missing queue name"));
+    let options = JobOptions::from_args(args);
+
+    let mut tx = pg_pool.begin().await.expect("failed to begin!");
+    if has_flag(args, "--reset") {
+        sqlx::query!("DELETE FROM queue_settings WHERE queue = $1", queue)
+            .execute(&mut tx)
+            .await
+            .expect("failed to reset the queue settings!");
+    }
+    let settings = sqlx::query!(
+        r#"
+        INSERT INTO queue_settings (queue, tenant, priority, max_attempts, retry_backoff_secs, timeout_secs)
+        VALUES ($1, $2, $3, $4, $5, $6)
+        ON CONFLICT (queue) DO UPDATE
+        SET tenant = COALESCE(EXCLUDED.tenant, queue_settings.tenant),
+            priority = COALESCE(EXCLUDED.priority, queue_settings.priority),
+            max_attempts = COALESCE(EXCLUDED.max_attempts, queue_settings.max_attempts),
+            retry_backoff_secs = COALESCE(EXCLUDED.retry_backoff_secs, queue_settings.retry_backoff_secs),
+            timeout_secs = COALESCE(EXCLUDED.timeout_secs, queue_settings.timeout_secs)
+        RETURNING tenant, priority, max_attempts, retry_backoff_secs, timeout_secs
+        "#,
+        queue,
+        options.tenant,
+        options.priority,
+        options.max_attempts,
+        options.retry_backoff_secs,
+        options.timeout_secs,
+    )
+    .fetch_one(&mut tx)
+    .await
+    .expect("failed to update the queue settings!");
+    tx.commit().await.expect("failed to commit!");
+
+    let show = |value: Option<String>| value.unwrap_or_else(|| "(worker's)".to_string());
+    println!("Jobs enqueued in '{}' default to:", queue);
+    println!(
+        "  tenant:         {}",
+        settings.tenant.unwrap_or_else(|| "default".to_string())
+    );
+    println!("  priority:       {}", settings.priority.unwrap_or(0));
+    println!(
+        "  max attempts:   {}",
+        show(settings.max_attempts.map(|n| n.to_string()))
+    );
+    println!(
+        "  retry backoff:  {}",
+        show(settings.retry_backoff_secs.map(|secs| format!("{}s", secs)))
+    );
+    println!(
+        "  timeout:        {}",
+        show(settings.timeout_secs.map(|secs| format!("{}s", secs)))
+    );
+}
+
+/// Sets how producers write the payloads of a type, then lists the types not
+/// internally tagged. Jobs already enqueued are stored internally tagged
+/// anyway, they are left alone.
+async fn payload_format(pg_pool: &PgPool, args: &[String]) {
+    if let Some((job_type, rest)) = args.split_first() {
+        match rest.first().map(String::as_str) {
+            Some("internal") => {
+                sqlx::query!("DELETE FROM payload_formats WHERE job_type = $1", job_type)
+                    .execute(pg_pool)
+                    .await
+                    .expect("failed to update the payload format!");
+            }
+            Some("adjacent") => {
+                sqlx::query!(
+                    r#"
+                    INSERT INTO payload_formats (job_type, representation)
+                    VALUES ($1, 'adjacent')
+                    ON CONFLICT (job_type) DO UPDATE SET representation = EXCLUDED.representation
+                    "#,
+                    job_type,
+                )
+                .execute(pg_pool)
+                .await
+                .expect("failed to update the payload format!");
+            }
+            Some(other) => usage(&format!("Unknown payload format: {}", other)),
+            None => usage("Missing payload format"),
+        }
+    }
+
+    let formats =
+        sqlx::query!("SELECT job_type, representation FROM payload_formats ORDER BY job_type")
+            .fetch_all(pg_pool)
+            .await
+            .expect("failed to list the payload formats!");
+    if formats.is_empty() {
+        println!("Every payload type is internally tagged");
+    }
+    for format in formats {
+        println!("{}: {}", format.job_type, format.representation);
+    }
+}
+
+/// Pauses a queue on a schedule, e.g. `--cron "0 8 * * 1-5" --duration 36000`
+/// keeps its jobs from running from 8am to 6pm (UTC) on weekdays.
+async fn add_window(pg_pool: &PgPool, args: &[String]) {
+    let queue = args
+        .first()
+        .filter(|arg| !arg.starts_with("--"))
+        .unwrap_or_else(|| usage("Missing queue name"));
+    let cron: maintenance::Cron = option_value(args, "--cron")
+        .unwrap_or_else(|| usage("Missing --cron"))
+        .parse()
+        .unwrap_or_else(|err| usage(&format!("Invalid cron expression: {}", err)));
+    let duration_secs = number_option(args, "--duration", |secs: &f64| *secs > 0.0)
+        .unwrap_or_else(|| usage("Missing --duration"));
+
+    let window = maintenance::add(pg_pool, queue, &cron, duration_secs)
+        .await
+        .expect("failed to add the window!");
+    if window.open {
+        println!(
+            "Window #{} pauses queue '{}' right away, until {}",
+            window.id, window.queue, window.closes_at
+        );
+    } else {
+        println!(
+            "Window #{} pauses queue '{}' from {} to {}",
+            window.id, window.queue, window.opens_at, window.closes_at
+        );
+    }
+}
+
+async fn list_windows(pg_pool: &PgPool) {
+    let windows = maintenance::list(pg_pool)
+        .await
+        .expect("failed to list windows!");
+    for window in windows {
+        println!(
+            "#{} queue '{}' every '{}' for {}s: {} {} to {}",
+            window.id,
+            window.queue,
+            window.cron,
+            window.duration_secs,
+            if window.open { "open" } else { "next" },
+            window.opens_at,
+            window.closes_at
+        );
+    }
+}
+
+async fn remove_window(pg_pool: &PgPool, args: &[String]) {
+    let id = args.first().unwrap_or_else(|| usage("Missing window id"));
+    let id = id
+        .parse()
+        .unwrap_or_else(|_| usage(&format!("Invalid window id: {}", id)));
+    let found = maintenance::remove(pg_pool, id)
+        .await
+        .expect("failed to remove the window!");
+    if !found {
+        usage(&format!("No such window: {}", id));
+    }
+    println!("Removed window #{}", id);
+}
+
+/// Moves maintenance windows on to their next occurrence as they close, and
+/// reports the queues they pause and resume. Windows only open again once
+/// moved: one scheduler must keep running, or `maintain` run often enough.
+///
+/// Also expires the jobs that failed for good, as `maintain` does, once an
+/// hour.
+async fn scheduler(pg_pool: &PgPool, args: &[String]) {
+    let interval = number_option(args, "--interval", |secs: &f64| *secs > 0.0).unwrap_or(30.0);
+    let (retention_days, archive) = retention_options(args);
+
+    let mut open = HashSet::new();
+    let mut next_expiry = tokio::time::Instant::now();
+    loop {
+        if tokio::time::Instant::now() >= next_expiry {
+            expire_dead_jobs(pg_pool, retention_days, archive).await;
+            next_expiry += std::time::Duration::from_secs(retention::EXPIRY_INTERVAL_SECS);
+        }
+        maintenance::advance(pg_pool)
+            .await
+            .expect("failed to advance windows!");
+        let windows = maintenance::list(pg_pool)
+            .await
+            .expect("failed to list windows!");
+        for window in &windows {
+            if window.open && open.insert(window.id) {
+                println!(
+                    "Queue '{}' paused until {} (window #{})",
+                    window.queue, window.closes_at, window.id
+                );
+            }
+        }
+        open.retain(|id| match windows.iter().find(|window| window.id == *id) {
+            Some(window) if window.open => true,
+            Some(window) => {
+                println!(
+                    "Queue '{}' resumed, paused again from {} (window #{})",
+                    window.queue, window.opens_at, window.id
+                );
+                false
+            }
+            None => {
+                println!("Window #{} was removed, its queue resumed", id);
+                false
+            }
+        });
+
+        tokio::time::sleep(std::time::Duration::from_secs_f64(interval)).await;
+    }
+}
+
+/// Closes a queue to new jobs, then waits for the ones it holds to be done
+/// with, e.g. before deploying workers that no longer understand them.
+async fn drain(pg_pool: &PgPool, args: &[String]) {
+    let queue = args
+        .first()
+        .filter(|arg| !arg.starts_with("--"))
+        .unwrap_or_else(|| usage("Missing queue name"));
+    let cancel = has_flag(args, "--cancel");
+    let timeout = option_value(args, "--timeout").map(|timeout| {
+        std::time::Duration::from_secs(
+            timeout
+                .parse()
+                .unwrap_or_else(|_| usage(&format!("Invalid timeout: {}", timeout))),
+        )
+    });
+
+    sqlx::query!(
+        r#"
+        INSERT INTO queues (name, draining)
+        VALUES ($1, $2)
+        ON CONFLICT (name) DO UPDATE SET draining = EXCLUDED.draining
+        "#,
+        queue,
+        !cancel,
+    )
+    .execute(pg_pool)
+    .await
+    .expect("failed to update the queue!");
+
+    if cancel {
+        println!("Queue '{}' takes new jobs again", queue);
+        return;
+    }
+
+    let started = std::time::Instant::now();
+    let mut last_pending = None;
+    loop {
+        let pending = sqlx::query_scalar!(
+            r#"
+            SELECT count(*) AS "count!"
+            FROM jobs
+            WHERE queue = $1
+              AND status IN ('Queued', 'Running')
+            "#,
+            queue,
+        )
+        .fetch_one(pg_pool)
+        .await
+        .expect("failed to count pending jobs!");
+
+        if pending == 0 {
+            println!("Queue '{}' drained in {:?}", queue, started.elapsed());
+            return;
+        }
+        if last_pending != Some(pending) {
+            println!("Draining queue '{}': {} job(s) pending", queue, pending);
+            last_pending = Some(pending);
+        }
+        if timeout.is_some_and(|timeout| started.elapsed() >= timeout) {
+            eprintln!(
+                "Timed out with {} job(s) still pending, queue '{}' is left draining",
+                pending, queue
+            );
+            std::process::exit(1);
+        }
+        tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+    }
+}
+
+async fn run_backfill(pg_pool: &PgPool, args: &[String]) {
+    let name = args
+        .first()
+        .unwrap_or_else(|| usage("Missing transform name"));
+    let transform =
+        backfill::transform(name).unwrap_or_else(|| usage(&format!("Unknown transform: {}", name)));
+    let batch_size = match option_value(args, "--batch-size") {
+        Some(batch_size) => batch_size
+            .parse()
+            .unwrap_or_else(|_| usage(&format!("Invalid batch size: {}", batch_size))),
+        None => 1000,
+    };
+
+    backfill::run(
+        pg_pool,
+        name,
+        transform,
+        batch_size,
+        has_flag(args, "--restart"),
+    )
+    .await
+    .expect("Backfill failed");
+}
+
+fn archive_path(args: &[String]) -> &str {
+    args.first()
+        .unwrap_or_else(|| usage("Missing archive file"))
+}
+
+async fn take_snapshot(pg_pool: &PgPool, args: &[String]) {
+    let path = archive_path(args);
+    let file = std::fs::File::create(path)
+        .unwrap_or_else(|err| usage(&format!("Cannot create {}: {}", path, err)));
+    let mut out = std::io::BufWriter::new(file);
+    let taken = snapshot::take(pg_pool, &mut out)
+        .await
+        .and_then(|taken| out.flush().map(|_| taken).map_err(sqlx::Error::Io));
+    let (header, rows) = taken.unwrap_or_else(|err| {
+        let _ = std::fs::remove_file(path);
+        panic!("failed to take the snapshot: {}", err)
+    });
+
+    let jobs = rows
+        .iter()
+        .find(|(table, _)| *table == "jobs")
+        .map_or(0, |(_, rows)| *rows);
+    println!(
+        "Snapshot of {} jobs taken at {} (migration {}), written to {}",
+        jobs, header.taken_at, header.schema_version, path
+    );
+}
+
+async fn restore_snapshot(pg_pool: &PgPool, args: &[String]) {
+    let path = archive_path(args);
+    let file = std::fs::File::open(path)
+        .unwrap_or_else(|err| usage(&format!("Cannot open {}: {}", path, err)));
+
+    match snapshot::restore(pg_pool, std::io::BufReader::new(file)).await {
+        Ok((header, restored)) => {
+            println!("Restored the snapshot taken at {}:", header.taken_at);
+            for (table, rows) in restored {
+                println!("  {:<16} {:>8} rows", table, rows);
+            }
+        }
+        Err(err) => {
+            eprintln!("Nothing restored: {}", err);
+            std::process::exit(1);
+        }
+    }
+}
+
+async fn show_stats(pg_pool: &PgPool, args: &[String]) {
+    if let Some(format) = option_value(args, "--format") {
+        if format != "csv" {
+            usage(&format!("Unknown format: {}", format));
+        }
+        let group_by: Vec<Dimension> = option_value(args, "--group-by")
+            .unwrap_or("day")
+            .split(',')
+            .map(|name| {
+                Dimension::parse(name.trim())
+                    .unwrap_or_else(|| usage(&format!("Unknown dimension: {}", name)))
+            })
+            .collect();
+        let aggregates = stats::aggregate(pg_pool, &group_by)
+            .await
+            .expect("failed to aggregate stats!");
+        print_csv(&group_by, &aggregates);
+        return;
+    }
+
+    let interval = match option_value(args, "--interval") {
+        Some(interval) => interval
+            .parse()
+            .unwrap_or_else(|_| usage(&format!("Invalid interval: {}", interval))),
+        None => 5,
+    };
+
+    loop {
+        let stats = stats::fetch(pg_pool).await.expect("failed to fetch stats!");
+
+        if has_flag(args, "--json") {
+            println!("{}", json!(stats));
+        } else {
+            let drain = match stats.estimated_drain_seconds {
+                Some(seconds) => format!("{:.0}s", seconds),
+                None => "never".to_string(),
+            };
+            println!(
+                "queued: {} | running: {} | failed: {} | done: {} | pending: {} | rate: {:.2}/s | drain: {}",
+                stats.queued,
+                stats.running,
+                stats.failed,
+                stats.done,
+                stats.pending,
+                stats.processing_rate,
+                drain
+            );
+            println!(
+                "  {:<16} {:>8} {:>8} {:>8} {:>8} {:>10} {:>12} {:>8}",
+                "type", "queued", "running", "failed", "done", "failures", "avg duration", "paused"
+            );
+            for type_stats in &stats.by_type {
+                let failure_rate = match type_stats.failure_rate {
+                    Some(rate) => format!("{:.1}%", rate * 100.0),
+                    None => "-".to_string(),
+                };
+                let duration = match type_stats.average_duration_seconds {
+                    Some(seconds) => format!("{:.3}s", seconds),
+                    None => "-".to_string(),
+                };
+                let paused = match type_stats.paused_for_seconds {
+                    Some(seconds) => format!("{:.0}s", seconds),
+                    None => "-".to_string(),
+                };
+                println!(
+                    "  {:<16} {:>8} {:>8} {:>8} {:>8} {:>10} {:>12} {:>8}",
+                    type_stats.job_type,
+                    type_stats.queued,
+                    type_stats.running,
+                    type_stats.failed,
+                    type_stats.done,
+                    failure_rate,
+                    duration,
+                    paused
+                );
+            }
+        }
+
+        if has_flag(args, "--once") {
+            return;
+        }
+        tokio::time::sleep(std::time::Duration::from_secs(interval)).await;
+    }
+}
+
+fn print_csv(group_by: &[Dimension], aggregates: &[Aggregate]) {
+    let header: Vec<&str> = group_by
+        .iter()
+        .map(|dimension| dimension.name())
+        .chain(["jobs", "failed", "average_duration_seconds"])
+        .collect();
+    println!("{}", header.join(","));
+
+    for aggregate in aggregates {
+        let row: Vec<String> = group_by
+            .iter()
+            .map(|dimension| csv_field(aggregate.dimension(*dimension).unwrap_or("")))
+            .chain([
+                aggregate.jobs.to_string(),
+                aggregate.failed.to_string(),
+                aggregate
+                    .average_duration_seconds
+                    .map(|seconds| seconds.to_string())
+                    .unwrap_or_default(),
+            ])
+            .collect();
+        println!("{}", row.join(","));
+    }
+}
+
+/// Queue names come from producers, they may need quoting.
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+async fn show_usage(pg_pool: &PgPool, args: &[String]) {
+    let hours = match option_value(args, "--hours") {
+        Some(hours) => hours
+            .parse()
+            .unwrap_or_else(|_| usage(&format!("Invalid number of hours: {}", hours))),
+        None => 24,
+    };
+    let rows = usage::fetch(pg_pool, hours, option_value(args, "--tenant"))
+        .await
+        .expect("failed to fetch usage!");
+
+    if has_flag(args, "--json") {
+        println!("{}", json!(rows));
+        return;
+    }
+
+    println!("Usage over the last {}h, heaviest first:", hours);
+    println!(
+        "  {:<16} {:<16} {:>8} {:>12}",
+        "tenant", "type", "attempts", "runtime"
+    );
+    for row in rows {
+        println!(
+            "  {:<16} {:<16} {:>8} {:>11.3}s",
+            row.tenant, row.job_type, row.attempts, row.runtime_seconds
+        );
+    }
+}
+
+/// Only reachable from the machine itself unless bound elsewhere. The job API
+/// token comes from the environment, not to show in the process list.
+async fn serve(pg_pool: &PgPool, args: &[String]) {
+    let address = match option_value(args, "--bind") {
+        Some(address) => address
+            .parse()
+            .unwrap_or_else(|_| usage(&format!("Invalid address: {}", address))),
+        None => IpAddr::V4(Ipv4Addr::LOCALHOST),
+    };
+    let port = match option_value(args, "--port") {
+        Some(port) => port
+            .parse()
+            .unwrap_or_else(|_| usage(&format!("Invalid port: {}", port))),
+        None => 9090,
+    };
+    let token = std::env::var("SQLX_PB_API_TOKEN")
+        .ok()
+        .filter(|token| !token.is_empty());
+    server::serve(pg_pool.clone(), address, port, token)
+        .await
+        .expect("Server failed");
+}
+
+/// Alerts go through `NOTIFY`, so only the ones raised while listening show.
+async fn alerts(pg_pool: &PgPool) {
+    let mut listener = PgListener::connect_with(pg_pool)
+        .await
+        .expect("failed to connect the listener!");
+    listener
+        .listen(breaker::ALERTS_CHANNEL)
+        .await
+        .expect("failed to listen for alerts!");
+
+    loop {
+        let notification = listener.recv().await.expect("failed to receive alerts!");
+        println!("{}", notification.payload());
+    }
+}
+
+async fn tree(pg_pool: &PgPool, args: &[String]) {
+    // Walks down the `parent_job_id` links, the path of ids is only used to
+    // print every child right below its parent.
+    let nodes = sqlx::query!(
+        r#"
+        WITH RECURSIVE tree AS (
+            SELECT id, status, payload, 0 AS depth, ARRAY[id] AS path
+            FROM jobs
+            WHERE id = $1
+            UNION ALL
+            SELECT jobs.id, jobs.status, jobs.payload, tree.depth + 1, tree.path || jobs.id
+            FROM jobs
+            JOIN tree ON jobs.parent_job_id = tree.id
+        )
+        SELECT id AS "id!", status AS "status!: JobStatus", payload AS "payload!: Json<Payload>", depth AS "depth!"
+        FROM tree
+        ORDER BY path
+        "#,
+        job_id(args),
+    )
+    .fetch_all(pg_pool)
+    .await
+    .expect("failed to walk the job tree!");
+
+    if nodes.is_empty() {
+        usage(&format!("No such job: {}", job_id(args)));
+    }
+
+    for node in nodes {
+        println!(
+            "{}#{} ({:?}) -> {:?}",
+            "    ".repeat(node.depth as usize),
+            node.id,
+            node.status,
+            node.payload.0
+        );
+    }
+}
+
+async fn inspect_job(pg_pool: &PgPool, args: &[String]) {
+    let inspection = inspect::fetch(pg_pool, job_id(args))
+        .await
+        .expect("failed to inspect the job!")
+        .unwrap_or_else(|| usage(&format!("No such job: {}", job_id(args))));
+
+    if has_flag(args, "--json") {
+        println!("{}", json!(inspection));
+        return;
+    }
+
+    let job = &inspection.job;
+    println!("Job #{} ({})", job.id, job.status);
+    println!("  queue:          {}", job.queue);
+    println!("  tenant:         {}", job.tenant);
+    println!("  priority:       {}", job.priority);
+    if job.keep {
+        println!("  kept:           yes, never expires");
+    }
+    if let Some(max_attempts) = job.max_attempts {
+        println!("  max attempts:   {}", max_attempts);
+    }
+    if let Some(backoff) = job.retry_backoff_secs {
+        println!("  retry backoff:  {}s", backoff);
+    }
+    if let Some(timeout) = job.timeout_secs {
+        println!("  timeout:        {}s", timeout);
+    }
+    println!(
+        "  type:           {}",
+        job.job_type.as_deref().unwrap_or("unknown")
+    );
+    match serde_json::from_value::<Payload>(job.payload.clone()) {
+        Ok(payload) => println!("  payload:        {:?}", payload),
+        Err(err) => println!(
+            "  payload:        {} (does not decode: {})",
+            job.payload, err
+        ),
+    }
+    match &job.params {
+        None => println!("  params:         none"),
+        Some(params) => match serde_json::from_value::<Params>(params.clone()) {
+            Ok(params) => println!("  params:         {:?}", params),
+            Err(err) => println!("  params:         {} (does not decode: {})", params, err),
+        },
+    }
+    println!("  tags:           {:?}", job.tags);
+    println!("  metadata:       {}", job.metadata);
+    if let Some(correlation_id) = &job.correlation_id {
+        println!("  correlation_id: {}", correlation_id);
+    }
+    if let Some(concurrency_key) = &job.concurrency_key {
+        println!("  concurrency:    {}", concurrency_key);
+    }
+    if let Some(workflow_id) = job.workflow_id {
+        println!(
+            "  workflow:       #{}, {}step {}",
+            workflow_id,
+            if job.workflow_compensation {
+                "compensating "
+            } else {
+                ""
+            },
+            job.workflow_step.unwrap_or_default()
+        );
+    }
+    println!("  attempts:       {}", job.attempts);
+    if let Some(last_error) = &job.last_error {
+        println!(
+            "  last error:     {}: {}",
+            job.error_kind.as_deref().unwrap_or("unknown"),
+            last_error
+        );
+    }
+    if let Some(checkpoint) = &job.checkpoint {
+        println!("  checkpoint:     {}", checkpoint);
+    }
+    println!("  created at:     {}", job.created_at);
+    println!("  run at:         {}", job.run_at);
+    for (label, at) in [
+        ("started at:  ", &job.started_at),
+        ("locked until:", &job.locked_until),
+        ("finished at: ", &job.finished_at),
+    ] {
+        if let Some(at) = at {
+            println!("  {}   {}", label, at);
+        }
+    }
+
+    let relative = |job: &Relative| {
+        format!(
+            "#{} ({}, {})",
+            job.id,
+            job.status,
+            job.job_type.as_deref().unwrap_or("unknown")
+        )
+    };
+    println!();
+    match &inspection.parent {
+        Some(parent) => println!("Parent: {}", relative(parent)),
+        None => println!("Parent: none"),
+    }
+    if inspection.children.is_empty() {
+        println!("Children: none");
+    } else {
+        println!("Children:");
+        for child in &inspection.children {
+            println!("  {}", relative(child));
+        }
+    }
+
+    if !inspection.effects.is_empty() {
+        println!();
+        println!("Effects:");
+        for effect in &inspection.effects {
+            println!(
+                "  {} = {} ({})",
+                effect.key, effect.result, effect.created_at
+            );
+        }
+    }
+
+    println!();
+    println!("History:");
+    for event in &inspection.history {
+        println!("  {}  {}", event.at, event.event);
+    }
+
+    if !inspection.logs.is_empty() {
+        println!();
+        println!("Logs:");
+        for log in &inspection.logs {
+            println!("  {}  attempt {} | {}", log.at, log.attempt, log.message);
+        }
+    }
+}
+
+/// Starts the "welcome" workflow: send an email, wait, then send a follow-up.
+/// Should the follow-up fail, a second email retracts the first one.
+async fn start_workflow(pg_pool: &PgPool, args: &[String]) {
+    let email = option_value(args, "--email").unwrap_or_else(|| usage("Missing --email"));
+    let wait = match option_value(args, "--wait") {
+        Some(wait) => wait
+            .parse()
+            .unwrap_or_else(|_| usage(&format!("Invalid wait: {}", wait))),
+        None => 60,
+    };
+
+    let steps = vec![
+        Step {
+            payload: Payload::SendEmail {
+                email: email.to_string(),
+                template: None,
+                attachments: vec![],
+            },
+            params: None,
+            delay_secs: 0,
+            compensation: Some(Compensation {
+                payload: Payload::SendEmail {
+                    email: email.to_string(),
+                    template: None,
+                    attachments: vec![],
+                },
+                params: None,
+            }),
+        },
+        Step {
+            payload: Payload::SendEmail {
+                email: email.to_string(),
+                template: None,
+                attachments: vec![],
+            },
+            params: Some(Params::FollowUp(false)),
+            delay_secs: wait,
+            compensation: None,
+        },
+    ];
+
+    let id = workflow::start(pg_pool, "welcome", steps)
+        .await
+        .expect("Could not start workflow");
+
+    println!("Started workflow #{}", id);
+}
+
+async fn show_workflow(pg_pool: &PgPool, args: &[String]) {
+    let workflow = sqlx::query!(
+        r#"
+        SELECT id, name, status AS "status: WorkflowStatus", current_step, steps AS "steps: Json<Vec<Step>>"
+        FROM workflows
+        WHERE id = $1
+        "#,
+        workflow_id(args),
+    )
+    .fetch_optional(pg_pool)
+    .await
+    .expect("failed to fetch workflow!")
+    .unwrap_or_else(|| usage(&format!("No such workflow: {}", workflow_id(args))));
+
+    println!(
+        "Workflow #{} '{}' ({:?}), step {}/{}",
+        workflow.id,
+        workflow.name,
+        workflow.status,
+        workflow.current_step,
+        workflow.steps.0.len()
+    );
+
+    let jobs = sqlx::query!(
+        r#"
+        SELECT id, workflow_step AS "step!", workflow_compensation AS compensation, status AS "status: JobStatus", run_at::TEXT AS "run_at!"
+        FROM jobs
+        WHERE workflow_id = $1
+        ORDER BY id
+        "#,
+        workflow.id,
+    )
+    .fetch_all(pg_pool)
+    .await
+    .expect("failed to fetch workflow jobs!");
+
+    for (index, step) in workflow.steps.0.iter().enumerate() {
+        let job = jobs
+            .iter()
+            .find(|job| !job.compensation && job.step as usize == index);
+        match job {
+            Some(job) => println!(
+                "  {}) {:?} -> job #{} ({:?}) at {}",
+                index, step.payload, job.id, job.status, job.run_at
+            ),
+            None => println!("  {}) {:?} -> not enqueued yet", index, step.payload),
+        }
+    }
+
+    for job in jobs.iter().filter(|job| job.compensation) {
+        println!(
+            "  compensating step {} -> job #{} ({:?}) at {}",
+            job.step, job.id, job.status, job.run_at
+        );
+    }
+}
@@ -0,0 +1,115 @@
+use std::path::PathBuf;
+use std::time::Duration;
+use std::time::SystemTime;
+
+use serde::Deserialize;
+use tokio::signal::unix::signal;
+use tokio::signal::unix::SignalKind;
+use tokio::sync::watch;
+
+use crate::plugin;
+use crate::store::QueueShares;
+
+/// The worker settings that can change while it runs. The database URL is
+/// not part of it: changing it requires a restart.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct WorkerConfig {
+    pub batch_size: i64,
+    /// Seconds to wait between claims when no job is due, `None` to exit
+    /// instead.
+    pub poll_secs: Option<u64>,
+    /// Payload types to claim, e.g. `SendEmail`, for workers specialized in
+    /// some jobs. Empty claims every type this worker can run.
+    pub job_types: Vec<String>,
+    /// Only claims jobs of at least that priority, for workers kept free for
+    /// urgent jobs.
+    pub min_priority: Option<i32>,
+    /// How claims are shared between queues: `{"weights": {"emails": 3},
+    /// "limits": {"reports": 2}}`.
+    pub queues: QueueShares,
+}
+
+impl Default for WorkerConfig {
+    fn default() -> Self {
+        WorkerConfig {
+            batch_size: 5,
+            poll_secs: None,
+            job_types: vec![],
+            min_priority: None,
+            queues: QueueShares::default(),
+        }
+    }
+}
+
+impl WorkerConfig {
+    /// `Wasm` jobs claimed by a worker that can't run plugins would only burn
+    /// their attempts.
+    pub fn check(&self) -> Result<(), String> {
+        if !plugin::ENABLED && self.job_types.iter().any(|job_type| job_type == "Wasm") {
+            return Err("Wasm jobs need a worker built with the `wasm` feature".to_string());
+        }
+        self.queues.check()
+    }
+
+    pub fn poll(&self) -> Option<Duration> {
+        self.poll_secs.map(Duration::from_secs)
+    }
+}
+
+/// How often the config file gets checked for changes.
+const WATCH_INTERVAL: Duration = Duration::from_secs(2);
+
+pub fn load(path: &PathBuf) -> Result<WorkerConfig, String> {
+    let content = std::fs::read_to_string(path)
+        .map_err(|err| format!("cannot read {}: {}", path.display(), err))?;
+    let config: WorkerConfig = serde_json::from_str(&content)
+        .map_err(|err| format!("invalid {}: {}", path.display(), err))?;
+    config
+        .check()
+        .map_err(|err| format!("invalid {}: {}", path.display(), err))?;
+    Ok(config)
+}
+
+fn modified(path: &PathBuf) -> Option<SystemTime> {
+    std::fs::metadata(path)
+        .and_then(|meta| meta.modified())
+        .ok()
+}
+
+/// Reloads the config file whenever it changes or on SIGHUP. An invalid file
+/// is reported and the current config kept.
+pub fn watch(path: PathBuf, config: WorkerConfig) -> watch::Receiver<WorkerConfig> {
+    let (sender, receiver) = watch::channel(config);
+    let mut hangup = signal(SignalKind::hangup()).expect("cannot listen to SIGHUP");
+
+    tokio::spawn(async move {
+        let mut last_modified = modified(&path);
+        loop {
+            tokio::select! {
+                _ = hangup.recv() => println!("SIGHUP received: reloading {}", path.display()),
+                _ = tokio::time::sleep(WATCH_INTERVAL) => {
+                    let current = modified(&path);
+                    if current == last_modified {
+                        continue;
+                    }
+                    last_modified = current;
+                    println!("{} changed: reloading", path.display());
+                }
+            }
+
+            match load(&path) {
+                Ok(config) if config == *sender.borrow() => println!("Configuration unchanged"),
+                Ok(config) => {
+                    println!("Configuration reloaded: {:?}", config);
+                    if sender.send(config).is_err() {
+                        return;
+                    }
+                }
+                Err(err) => println!("Keeping the current configuration, {}", err),
+            }
+        }
+    });
+
+    receiver
+}
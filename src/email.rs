@@ -0,0 +1,52 @@
+use std::io;
+use std::sync::OnceLock;
+
+use minijinja::path_loader;
+use minijinja::Environment;
+use minijinja::UndefinedBehavior;
+use serde::Deserialize;
+use serde::Serialize;
+use serde_json::Map;
+use serde_json::Value;
+
+/// Where templates are read from, relative to the worker's working directory:
+/// the `welcome` template is `templates/email/welcome.txt`.
+const TEMPLATES_DIR: &str = "templates/email";
+
+/// Which template an email is rendered from, and with what.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Template {
+    pub name: String,
+    #[serde(default)]
+    pub variables: Map<String, Value>,
+}
+
+pub struct Email {
+    pub subject: String,
+    pub body: String,
+}
+
+/// Templates are loaded on first use, then kept: the worker must be restarted
+/// for an edited template to be picked up.
+fn environment() -> &'static Environment<'static> {
+    static ENVIRONMENT: OnceLock<Environment<'static>> = OnceLock::new();
+    ENVIRONMENT.get_or_init(|| {
+        let mut env = Environment::new();
+        env.set_loader(path_loader(TEMPLATES_DIR));
+        // A missing variable fails the job, instead of sending "Hello ,".
+        env.set_undefined_behavior(UndefinedBehavior::Strict);
+        env
+    })
+}
+
+/// Renders the `subject` and `body` blocks of the template.
+pub fn render(template: &Template) -> Result<Email, minijinja::Error> {
+    let source = environment().get_template(&format!("{}.txt", template.name))?;
+    let mut captured = source.render_captured_to(&template.variables, io::sink())?;
+    captured.with_state_mut(|state| {
+        Ok(Email {
+            subject: state.render_block("subject")?.trim().to_string(),
+            body: state.render_block("body")?.trim().to_string(),
+        })
+    })
+}
@@ -0,0 +1,111 @@
+use std::net::TcpListener;
+use std::path::Path;
+use std::process::Child;
+use std::process::Command;
+use std::process::Stdio;
+use std::sync::OnceLock;
+use std::time::Duration;
+
+use sqlx::Connection;
+use sqlx::PgConnection;
+
+use crate::schema;
+
+/// Keeps the server's stdin open: once the tests are over and the process
+/// exits, the pipe closes and the server is stopped, its data removed.
+static SERVER: OnceLock<(String, Child)> = OnceLock::new();
+
+/// The URL of a throwaway Postgres, started and migrated on first use, for
+/// `cargo test` to run without the docker-compose database.
+///
+/// It needs `initdb` and `postgres` on the `PATH`, but no running server: the
+/// cluster lives in a temporary directory and listens on a free local port.
+pub fn url() -> &'static str {
+    let (url, _) = SERVER.get_or_init(|| {
+        let dir = std::env::temp_dir().join(format!("sqlx-pb-test-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        init(&dir);
+        let port = free_port();
+        let server = start(&dir, port);
+        let url = format!("postgres://postgres@127.0.0.1:{}/postgres", port);
+        migrate(&url);
+        (url, server)
+    });
+    url
+}
+
+fn init(dir: &Path) {
+    let status = Command::new("initdb")
+        .args([
+            "--username=postgres",
+            "--auth=trust",
+            "--encoding=UTF8",
+            "-D",
+        ])
+        .arg(dir)
+        .stdout(Stdio::null())
+        .status()
+        .expect("dev-embedded-pg needs initdb on the PATH");
+    assert!(status.success(), "initdb failed");
+}
+
+/// Asks the OS for a port nothing listens on, which is still free a moment
+/// later unless something else grabbed it meanwhile.
+fn free_port() -> u16 {
+    TcpListener::bind("127.0.0.1:0")
+        .and_then(|listener| listener.local_addr())
+        .expect("no free port")
+        .port()
+}
+
+/// Durability is off: the data is thrown away anyway.
+fn start(dir: &Path, port: u16) -> Child {
+    Command::new("sh")
+        .arg("-c")
+        .arg(r#"postgres "$@" & pg=$!; read _; kill -INT $pg; wait $pg; rm -rf "$0""#)
+        .arg(dir)
+        .arg("-D")
+        .arg(dir)
+        .args(["-c", "listen_addresses=127.0.0.1", "-c", "fsync=off"])
+        .args(["-c", "full_page_writes=off", "-c", "synchronous_commit=off"])
+        .arg("-k")
+        .arg(dir)
+        .arg("-p")
+        .arg(port.to_string())
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .expect("dev-embedded-pg needs postgres on the PATH")
+}
+
+/// Waits for the server to accept connections, then applies the migrations.
+/// Tests each run on their own runtime, this one only lives for the setup.
+fn migrate(url: &str) {
+    let url = url.to_string();
+    std::thread::spawn(move || {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        runtime.block_on(async {
+            let mut attempts = 0;
+            let mut conn = loop {
+                match PgConnection::connect(&url).await {
+                    Ok(conn) => break conn,
+                    Err(err) if attempts == 100 => panic!("Postgres didn't start: {}", err),
+                    Err(_) => {
+                        attempts += 1;
+                        tokio::time::sleep(Duration::from_millis(100)).await;
+                    }
+                }
+            };
+            schema::MIGRATOR
+                .run(&mut conn)
+                .await
+                .expect("failed to apply the migrations");
+        })
+    })
+    .join()
+    .expect("failed to set up the embedded Postgres");
+}
@@ -0,0 +1,103 @@
+use std::any::Any;
+use std::fmt;
+
+use serde::Deserialize;
+use serde::Serialize;
+
+use crate::retry;
+
+/// Why a job failed, which decides whether it is worth running again.
+#[derive(sqlx::Type, Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[sqlx(type_name = "ERROR_KIND")]
+pub enum ErrorKind {
+    /// Might go through on a later attempt: a flaky dependency, a lost
+    /// connection...
+    Retryable,
+    /// Will fail the same way however many times it runs: a bad payload...
+    Permanent,
+    /// Ran out of time, retried like a retryable error.
+    Timeout,
+    /// The handler panicked, not retried as it is likely to panic again.
+    Panic,
+}
+
+impl ErrorKind {
+    pub const ALL: [ErrorKind; 4] = [
+        ErrorKind::Retryable,
+        ErrorKind::Permanent,
+        ErrorKind::Timeout,
+        ErrorKind::Panic,
+    ];
+
+    pub fn is_retryable(self) -> bool {
+        matches!(self, ErrorKind::Retryable | ErrorKind::Timeout)
+    }
+}
+
+/// What a handler fails with. Database errors convert into it, as retryable
+/// when transient and permanent otherwise.
+#[derive(Debug)]
+pub struct JobError {
+    pub kind: ErrorKind,
+    pub message: String,
+}
+
+impl JobError {
+    pub fn retryable(message: impl Into<String>) -> Self {
+        JobError {
+            kind: ErrorKind::Retryable,
+            message: message.into(),
+        }
+    }
+
+    pub fn permanent(message: impl Into<String>) -> Self {
+        JobError {
+            kind: ErrorKind::Permanent,
+            message: message.into(),
+        }
+    }
+
+    pub fn timeout(message: impl Into<String>) -> Self {
+        JobError {
+            kind: ErrorKind::Timeout,
+            message: message.into(),
+        }
+    }
+}
+
+impl JobError {
+    /// From what a panicking handler unwound with, which is its message when
+    /// it panicked with one.
+    pub fn panic(payload: Box<dyn Any + Send>) -> Self {
+        let message = match payload.downcast::<String>() {
+            Ok(message) => *message,
+            Err(payload) => match payload.downcast::<&str>() {
+                Ok(message) => message.to_string(),
+                Err(_) => "unknown panic".to_string(),
+            },
+        };
+        JobError {
+            kind: ErrorKind::Panic,
+            message,
+        }
+    }
+}
+
+impl fmt::Display for JobError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}: {}", self.kind, self.message)
+    }
+}
+
+impl From<sqlx::Error> for JobError {
+    fn from(err: sqlx::Error) -> Self {
+        JobError {
+            kind: if retry::is_transient(&err) {
+                ErrorKind::Retryable
+            } else {
+                ErrorKind::Permanent
+            },
+            message: err.to_string(),
+        }
+    }
+}
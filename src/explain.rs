@@ -0,0 +1,143 @@
+use serde_json::Value;
+use sqlx::types::Json;
+use sqlx::Execute;
+use sqlx::PgPool;
+use sqlx::Postgres;
+
+/// A sequential scan reading fewer rows than that is cheap enough, whether
+/// or not an index could serve it.
+const SEQ_SCAN_ROWS: f64 = 1000.0;
+
+/// How far off the planner's row estimate may be before its statistics are
+/// deemed stale.
+const ESTIMATE_FACTOR: f64 = 10.0;
+
+/// What running a query told about its plan.
+pub struct Analysis {
+    /// One line per plan node, indented by depth.
+    pub plan: Vec<String>,
+    pub planning_ms: f64,
+    pub execution_ms: f64,
+    pub warnings: Vec<String>,
+}
+
+/// Runs `query` under `EXPLAIN (ANALYZE, BUFFERS)`, against the data as it
+/// is, within a transaction that is rolled back: the claim query really
+/// claims jobs while being analyzed, that must not stick.
+pub async fn analyze<'q>(
+    pg_pool: &PgPool,
+    mut query: impl Execute<'q, Postgres>,
+) -> Result<Analysis, sqlx::Error> {
+    let sql = format!("EXPLAIN (ANALYZE, BUFFERS, FORMAT JSON) {}", query.sql());
+    let arguments = query.take_arguments().unwrap_or_default();
+
+    let mut tx = pg_pool.begin().await?;
+    let Json(explained): Json<Value> = sqlx::query_scalar_with(&sql, arguments)
+        .fetch_one(&mut tx)
+        .await?;
+    tx.rollback().await?;
+
+    let explained = &explained[0];
+    let mut analysis = Analysis {
+        plan: vec![],
+        planning_ms: explained["Planning Time"].as_f64().unwrap_or_default(),
+        execution_ms: explained["Execution Time"].as_f64().unwrap_or_default(),
+        warnings: vec![],
+    };
+    visit(&explained["Plan"], 0, &mut analysis);
+    Ok(analysis)
+}
+
+fn number(node: &Value, key: &str) -> f64 {
+    node[key].as_f64().unwrap_or_default()
+}
+
+/// `Seq Scan on jobs`, `Index Scan using jobs_pkey on jobs`...
+fn describe(node: &Value) -> String {
+    let mut description = node["Node Type"].as_str().unwrap_or("?").to_string();
+    if let Some(index) = node["Index Name"].as_str() {
+        description.push_str(&format!(" using {}", index));
+    }
+    if let Some(relation) = node["Relation Name"].as_str() {
+        description.push_str(&format!(" on {}", relation));
+        if let Some(alias) = node["Alias"].as_str().filter(|alias| *alias != relation) {
+            description.push_str(&format!(" {}", alias));
+        }
+    }
+    description
+}
+
+fn visit(node: &Value, depth: usize, analysis: &mut Analysis) {
+    let description = describe(node);
+    let loops = number(node, "Actual Loops").max(1.0);
+    let rows = number(node, "Actual Rows") * loops;
+    let removed = number(node, "Rows Removed by Filter") * loops;
+    let estimated = number(node, "Plan Rows") * loops;
+
+    let subplan = node["Subplan Name"]
+        .as_str()
+        .map(|name| format!("[{}] ", name))
+        .unwrap_or_default();
+    analysis.plan.push(format!(
+        "{}{}{}: {} rows in {:.3} ms, buffers hit {} read {}",
+        "  ".repeat(depth),
+        subplan,
+        description,
+        rows,
+        number(node, "Actual Total Time"),
+        number(node, "Shared Hit Blocks"),
+        number(node, "Shared Read Blocks"),
+    ));
+
+    let filter = node["Filter"]
+        .as_str()
+        .map(|filter| format!(" (filter: {})", filter))
+        .unwrap_or_default();
+    let node_type = node["Node Type"].as_str().unwrap_or_default();
+    if node_type == "Seq Scan" && rows + removed >= SEQ_SCAN_ROWS {
+        analysis.warnings.push(if removed > 0.0 {
+            format!(
+                "{} read {} rows to keep {}{}: an index on the filtered columns would avoid it",
+                description,
+                rows + removed,
+                rows,
+                filter
+            )
+        } else {
+            format!(
+                "{} read all of its {} rows, and will get slower as the table grows",
+                description, rows
+            )
+        });
+    } else if node_type.starts_with("Index")
+        && removed >= SEQ_SCAN_ROWS
+        && removed > rows * ESTIMATE_FACTOR
+    {
+        analysis.warnings.push(format!(
+            "{} discarded {} rows to keep {}{}: the index doesn't cover that filter",
+            description, removed, rows, filter
+        ));
+    }
+
+    if let Some(relation) = node["Relation Name"].as_str() {
+        let (low, high) = (rows.min(estimated), rows.max(estimated));
+        if high >= SEQ_SCAN_ROWS / 10.0 && high > low.max(1.0) * ESTIMATE_FACTOR {
+            analysis.warnings.push(format!(
+                "{} was estimated at {} rows, got {}: statistics may be stale, run ANALYZE {}",
+                description, estimated, rows, relation
+            ));
+        }
+    }
+
+    if node["Sort Space Type"] == "Disk" {
+        analysis.warnings.push(format!(
+            "{} spilled {} kB to disk: raise work_mem, or sort fewer rows",
+            description,
+            number(node, "Sort Space Used")
+        ));
+    }
+
+    for child in node["Plans"].as_array().into_iter().flatten() {
+        visit(child, depth + 1, analysis);
+    }
+}
@@ -0,0 +1,229 @@
+use std::cell::Cell;
+use std::time::Duration;
+use std::time::Instant;
+
+use serde_json::Value;
+
+use crate::error::JobError;
+use crate::store::JobStore;
+use crate::store::QueueShares;
+use crate::JobRow;
+use crate::JobStatus;
+use crate::Params;
+use crate::Payload;
+
+/// How long a failing source is skipped, doubling with each consecutive
+/// failure up to `MAX_BACKOFF`.
+const BASE_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+/// One of the databases a federated worker claims from, e.g. a region's.
+struct Source<S> {
+    name: String,
+    store: S,
+    /// Consecutive failures, 0 while healthy.
+    failures: Cell<u32>,
+    /// Skipped until then after a failure.
+    retry_at: Cell<Option<Instant>>,
+}
+
+impl<S> Source<S> {
+    fn available(&self) -> bool {
+        self.retry_at
+            .get()
+            .is_none_or(|retry_at| Instant::now() >= retry_at)
+    }
+
+    fn succeeded(&self) {
+        if self.failures.replace(0) > 0 {
+            println!("Source {} is back", self.name);
+        }
+        self.retry_at.set(None);
+    }
+
+    fn failed(&self, err: &sqlx::Error) {
+        let failures = self.failures.get() + 1;
+        self.failures.set(failures);
+        let backoff = BASE_BACKOFF
+            .saturating_mul(2u32.saturating_pow(failures - 1))
+            .min(MAX_BACKOFF);
+        self.retry_at.set(Some(Instant::now() + backoff));
+        println!(
+            "Source {} failed ({}), skipped for {:?}",
+            self.name, err, backoff
+        );
+    }
+}
+
+/// Claims from several databases in turn, so that one worker drains the job
+/// tables of them all. A source that fails is skipped for a while, the
+/// others keep being worked on.
+///
+/// Job ids are only unique within a database: each batch is claimed from a
+/// single source, and everything about its jobs goes back to that source.
+pub struct FederatedJobStore<S> {
+    sources: Vec<Source<S>>,
+    /// The source the next claim is tried on first.
+    next: Cell<usize>,
+    /// The source of the batch being worked on.
+    current: Cell<usize>,
+}
+
+impl<S: JobStore> FederatedJobStore<S> {
+    pub fn new(sources: Vec<(String, S)>) -> Self {
+        assert!(!sources.is_empty(), "no source to claim from");
+        FederatedJobStore {
+            sources: sources
+                .into_iter()
+                .map(|(name, store)| Source {
+                    name,
+                    store,
+                    failures: Cell::new(0),
+                    retry_at: Cell::new(None),
+                })
+                .collect(),
+            next: Cell::new(0),
+            current: Cell::new(0),
+        }
+    }
+
+    fn current(&self) -> &S {
+        &self.sources[self.current.get()].store
+    }
+}
+
+impl<S: JobStore> JobStore for FederatedJobStore<S> {
+    /// Tries each available source in turn, starting after the one the last
+    /// batch came from, until one has due jobs. Failures only count against
+    /// the source's health: the worker waits for the next poll when no source
+    /// answers.
+    async fn claim(
+        &self,
+        batch_size: i64,
+        job_types: &[String],
+        min_priority: Option<i32>,
+        queues: &QueueShares,
+    ) -> Result<Vec<JobRow>, sqlx::Error> {
+        let count = self.sources.len();
+        for offset in 0..count {
+            let index = (self.next.get() + offset) % count;
+            let source = &self.sources[index];
+            if !source.available() {
+                continue;
+            }
+
+            match source
+                .store
+                .claim(batch_size, job_types, min_priority, queues)
+                .await
+            {
+                Ok(jobs) => {
+                    source.succeeded();
+                    if !jobs.is_empty() {
+                        println!("Claimed {} job(s) from {}", jobs.len(), source.name);
+                        self.current.set(index);
+                        self.next.set((index + 1) % count);
+                        return Ok(jobs);
+                    }
+                }
+                Err(err) => source.failed(&err),
+            }
+        }
+        Ok(vec![])
+    }
+
+    async fn reap(&self) -> Result<u64, sqlx::Error> {
+        let mut reaped = 0;
+        for source in self.sources.iter().filter(|source| source.available()) {
+            match source.store.reap().await {
+                Ok(count) => reaped += count,
+                Err(err) => source.failed(&err),
+            }
+        }
+        Ok(reaped)
+    }
+
+    async fn release(&self, jobs: &[(i64, i32)], started: bool) -> Result<(), sqlx::Error> {
+        self.current().release(jobs, started).await
+    }
+
+    async fn finish(
+        &self,
+        job_id: i64,
+        attempt: i32,
+        status: JobStatus,
+    ) -> Result<bool, sqlx::Error> {
+        self.current().finish(job_id, attempt, status).await
+    }
+
+    async fn fail(
+        &self,
+        job_id: i64,
+        attempt: i32,
+        error: &JobError,
+        retry_in: Option<f64>,
+    ) -> Result<bool, sqlx::Error> {
+        self.current().fail(job_id, attempt, error, retry_in).await
+    }
+
+    async fn checkpoint(
+        &self,
+        job_id: i64,
+        attempt: i32,
+        state: Value,
+    ) -> Result<bool, sqlx::Error> {
+        self.current().checkpoint(job_id, attempt, state).await
+    }
+
+    async fn enqueue_child(
+        &self,
+        parent_job_id: i64,
+        correlation_id: Option<&str>,
+        payload: &Payload,
+        params: Option<&Params>,
+    ) -> Result<i64, sqlx::Error> {
+        self.current()
+            .enqueue_child(parent_job_id, correlation_id, payload, params)
+            .await
+    }
+
+    async fn effect(&self, job_id: i64, key: &str) -> Result<Option<Value>, sqlx::Error> {
+        self.current().effect(job_id, key).await
+    }
+
+    async fn record_effect(
+        &self,
+        job_id: i64,
+        key: &str,
+        result: Value,
+    ) -> Result<(), sqlx::Error> {
+        self.current().record_effect(job_id, key, result).await
+    }
+
+    async fn blob(&self, blob_id: i64) -> Result<Option<Vec<u8>>, sqlx::Error> {
+        self.current().blob(blob_id).await
+    }
+
+    async fn record_logs(
+        &self,
+        job_id: i64,
+        attempt: i32,
+        lines: &[String],
+    ) -> Result<(), sqlx::Error> {
+        self.current().record_logs(job_id, attempt, lines).await
+    }
+
+    async fn start(&self, job_id: i64, attempt: i32) -> Result<bool, sqlx::Error> {
+        self.current().start(job_id, attempt).await
+    }
+
+    /// Every source may have begun a batch, if only by reaping.
+    async fn end_batch(&self) -> Result<(), sqlx::Error> {
+        for source in &self.sources {
+            if let Err(err) = source.store.end_batch().await {
+                source.failed(&err);
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,179 @@
+use serde::Serialize;
+use serde_json::Value;
+use sqlx::PgPool;
+
+/// Everything the database knows about one job, gathered in one place.
+#[derive(Serialize, Debug)]
+pub struct Inspection {
+    pub job: Job,
+    pub parent: Option<Relative>,
+    pub children: Vec<Relative>,
+    pub effects: Vec<Effect>,
+    /// What happened to the job over all its attempts, oldest first, then
+    /// what is due next.
+    pub history: Vec<Event>,
+    /// What its handler logged, oldest first.
+    pub logs: Vec<Log>,
+}
+
+/// The full row. The payload and params are kept as stored, rows written by
+/// an older version may not decode anymore and are worth looking at anyway.
+#[derive(Serialize, Debug)]
+pub struct Job {
+    pub id: i64,
+    pub status: String,
+    pub queue: String,
+    pub tenant: String,
+    pub priority: i32,
+    pub keep: bool,
+    pub job_type: Option<String>,
+    pub payload: Value,
+    pub params: Option<Value>,
+    pub tags: Vec<String>,
+    pub metadata: Value,
+    pub correlation_id: Option<String>,
+    pub concurrency_key: Option<String>,
+    pub parent_job_id: Option<i64>,
+    pub workflow_id: Option<i64>,
+    pub workflow_step: Option<i32>,
+    pub workflow_compensation: bool,
+    pub attempts: i32,
+    pub max_attempts: Option<i32>,
+    pub retry_backoff_secs: Option<f64>,
+    pub timeout_secs: Option<f64>,
+    pub checkpoint: Option<Value>,
+    pub last_error: Option<String>,
+    pub error_kind: Option<String>,
+    pub created_at: String,
+    pub run_at: String,
+    pub started_at: Option<String>,
+    pub locked_until: Option<String>,
+    pub finished_at: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct Relative {
+    pub id: i64,
+    pub status: String,
+    pub job_type: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct Effect {
+    pub key: String,
+    pub result: Value,
+    pub created_at: String,
+}
+
+#[derive(Serialize, Debug)]
+pub struct Event {
+    pub at: String,
+    pub event: String,
+}
+
+#[derive(Serialize, Debug)]
+pub struct Log {
+    pub at: String,
+    pub attempt: i32,
+    pub message: String,
+}
+
+/// Returns `None` when there is no such job.
+pub async fn fetch(pg_pool: &PgPool, job_id: i64) -> Result<Option<Inspection>, sqlx::Error> {
+    let job = sqlx::query_as!(
+        Job,
+        r#"
+        SELECT id, status::TEXT AS "status!", queue, tenant, priority, keep, job_type, payload, params, tags, metadata,
+               correlation_id, concurrency_key, parent_job_id, workflow_id, workflow_step, workflow_compensation,
+               attempts, max_attempts, retry_backoff_secs, timeout_secs, checkpoint, last_error, error_kind::TEXT AS error_kind,
+               created_at::TEXT AS "created_at!", run_at::TEXT AS "run_at!",
+               started_at::TEXT AS started_at, locked_until::TEXT AS locked_until,
+               finished_at::TEXT AS finished_at
+        FROM jobs
+        WHERE id = $1
+        "#,
+        job_id,
+    )
+    .fetch_optional(pg_pool)
+    .await?;
+
+    let job = match job {
+        Some(job) => job,
+        None => return Ok(None),
+    };
+
+    let parent = sqlx::query_as!(
+        Relative,
+        r#"SELECT id, status::TEXT AS "status!", job_type FROM jobs WHERE id = $1"#,
+        job.parent_job_id,
+    )
+    .fetch_optional(pg_pool)
+    .await?;
+
+    let children = sqlx::query_as!(
+        Relative,
+        r#"SELECT id, status::TEXT AS "status!", job_type FROM jobs WHERE parent_job_id = $1 ORDER BY id"#,
+        job_id,
+    )
+    .fetch_all(pg_pool)
+    .await?;
+
+    let effects = sqlx::query_as!(
+        Effect,
+        r#"SELECT key, result, created_at::TEXT AS "created_at!" FROM effects WHERE job_id = $1 ORDER BY created_at, key"#,
+        job_id,
+    )
+    .fetch_all(pg_pool)
+    .await?;
+
+    // The recorded events, along with the effects and children, then the
+    // upcoming ones (lease expiry, next attempt) at the time they are due.
+    let history = sqlx::query_as!(
+        Event,
+        r#"
+        SELECT at::TEXT AS "at!", event AS "event!"
+        FROM (
+            SELECT at, message AS event, 0 AS rank, id
+            FROM job_events WHERE job_id = $1 AND kind = 'event'
+            UNION ALL
+            SELECT created_at, format('effect %L recorded', key), 1, NULL
+            FROM effects WHERE job_id = $1
+            UNION ALL
+            SELECT created_at, format('child #%s enqueued', id), 1, id
+            FROM jobs WHERE parent_job_id = $1
+            UNION ALL
+            SELECT locked_until, 'lease expires', 2, NULL
+            FROM jobs WHERE id = $1 AND status = 'Running'
+            UNION ALL
+            SELECT run_at, format('attempt %s due', attempts + 1), 2, NULL
+            FROM jobs WHERE id = $1 AND status = 'Queued' AND run_at > now()
+        ) events
+        ORDER BY rank = 2, at, rank, id
+        "#,
+        job_id,
+    )
+    .fetch_all(pg_pool)
+    .await?;
+
+    let logs = sqlx::query_as!(
+        Log,
+        r#"
+        SELECT at::TEXT AS "at!", attempt, message
+        FROM job_events
+        WHERE job_id = $1 AND kind = 'log'
+        ORDER BY id
+        "#,
+        job_id,
+    )
+    .fetch_all(pg_pool)
+    .await?;
+
+    Ok(Some(Inspection {
+        job,
+        parent,
+        children,
+        effects,
+        history,
+        logs,
+    }))
+}
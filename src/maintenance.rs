@@ -0,0 +1,279 @@
+use std::fmt;
+use std::str::FromStr;
+
+use sqlx::PgPool;
+
+/// Far enough to find the next February 29th, however the expression got
+/// there.
+const MAX_SEARCH_DAYS: i64 = 5 * 366;
+
+/// A 5-field cron expression (minute, hour, day of month, month, day of
+/// week), each field being `*`, a value, a range or a comma separated list
+/// of them, optionally stepped with `/n`. Times are UTC.
+///
+/// As with cron, a day matches either day field when both are restricted,
+/// `0 3 1 * 1` being 3am on the 1st of the month and on Mondays.
+pub struct Cron {
+    source: String,
+    minutes: u64,
+    hours: u64,
+    days_of_month: u64,
+    months: u64,
+    days_of_week: u64,
+    any_day_of_month: bool,
+    any_day_of_week: bool,
+}
+
+/// The values matched by one field, as bits.
+fn parse_field(field: &str, min: u32, max: u32) -> Result<u64, String> {
+    let number = |value: &str| {
+        value
+            .parse::<u32>()
+            .map_err(|_| format!("invalid value: {}", value))
+    };
+    let mut bits = 0;
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => match number(step)? {
+                0 => return Err(format!("invalid step: {}", part)),
+                step => (range, step),
+            },
+            None => (part, 1),
+        };
+        let (start, end) = match range.split_once('-') {
+            _ if range == "*" => (min, max),
+            Some((start, end)) => (number(start)?, number(end)?),
+            // `5/15` is every 15 from 5 on.
+            None if step > 1 => (number(range)?, max),
+            None => (number(range)?, number(range)?),
+        };
+        if start < min || end > max || start > end {
+            return Err(format!("out of range {}-{}: {}", min, max, part));
+        }
+        for value in (start..=end).step_by(step as usize) {
+            bits |= 1 << value;
+        }
+    }
+    Ok(bits)
+}
+
+fn has(bits: u64, value: i64) -> bool {
+    bits & (1 << value) != 0
+}
+
+/// The month and day of `days` since 1970-01-01, after Howard Hinnant's
+/// `civil_from_days`.
+fn month_and_day(days: i64) -> (i64, i64) {
+    let days = days + 719_468;
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    };
+    (month, day)
+}
+
+impl Cron {
+    fn matches_day(&self, days: i64) -> bool {
+        let (month, day) = month_and_day(days);
+        // 1970-01-01 was a Thursday.
+        let weekday = (days + 4).rem_euclid(7);
+        let day_of_month = has(self.days_of_month, day);
+        let day_of_week = has(self.days_of_week, weekday);
+        has(self.months, month)
+            && match (self.any_day_of_month, self.any_day_of_week) {
+                (false, false) => day_of_month || day_of_week,
+                _ => day_of_month && day_of_week,
+            }
+    }
+
+    fn search(&self, after: i64) -> Option<i64> {
+        let mut minute = after.div_euclid(60) + 1;
+        let limit = minute + MAX_SEARCH_DAYS * 1440;
+        while minute < limit {
+            let days = minute.div_euclid(1440);
+            let hour = minute.rem_euclid(1440) / 60;
+            if !self.matches_day(days) {
+                minute = (days + 1) * 1440;
+            } else if !has(self.hours, hour) {
+                minute = days * 1440 + (hour + 1) * 60;
+            } else if !has(self.minutes, minute.rem_euclid(60)) {
+                minute += 1;
+            } else {
+                return Some(minute * 60);
+            }
+        }
+        None
+    }
+
+    /// The first time it matches strictly after `after`, both in seconds
+    /// since the epoch.
+    pub fn next_after(&self, after: i64) -> i64 {
+        self.search(after)
+            .expect("parsing checked that the expression matches")
+    }
+}
+
+impl FromStr for Cron {
+    type Err = String;
+
+    fn from_str(source: &str) -> Result<Self, Self::Err> {
+        let fields: Vec<&str> = source.split_whitespace().collect();
+        let [minute, hour, day_of_month, month, day_of_week] = fields[..] else {
+            return Err(format!("expected 5 fields, got {}", fields.len()));
+        };
+        let days_of_week = parse_field(day_of_week, 0, 7)?;
+        let cron = Cron {
+            source: fields.join(" "),
+            minutes: parse_field(minute, 0, 59)?,
+            hours: parse_field(hour, 0, 23)?,
+            days_of_month: parse_field(day_of_month, 1, 31)?,
+            months: parse_field(month, 1, 12)?,
+            // Sunday is both 0 and 7.
+            days_of_week: (days_of_week | days_of_week >> 7) & 0x7f,
+            any_day_of_month: day_of_month.starts_with('*'),
+            any_day_of_week: day_of_week.starts_with('*'),
+        };
+        // Matching once within the search span, it matches within it from
+        // any point: calendars repeat sooner.
+        match cron.search(0) {
+            Some(_) => Ok(cron),
+            None => Err("never matches".to_string()),
+        }
+    }
+}
+
+impl fmt::Display for Cron {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.source)
+    }
+}
+
+/// A maintenance window, on its current or next occurrence.
+pub struct Window {
+    pub id: i64,
+    pub queue: String,
+    pub cron: String,
+    pub duration_secs: f64,
+    pub opens_at: String,
+    pub closes_at: String,
+    /// Whether the queue is paused by it right now.
+    pub open: bool,
+}
+
+/// The occurrence that is open at `now`, or else the next one. Occurrences
+/// that began less than `duration_secs` ago are still open.
+fn current_or_next(cron: &Cron, now: f64, duration_secs: f64) -> f64 {
+    cron.next_after((now - duration_secs).floor() as i64) as f64
+}
+
+/// Per the database's clock, which claiming goes by.
+async fn now(pg_pool: &PgPool) -> Result<f64, sqlx::Error> {
+    sqlx::query_scalar!(r#"SELECT EXTRACT(EPOCH FROM now())::FLOAT8 AS "now!""#)
+        .fetch_one(pg_pool)
+        .await
+}
+
+/// Pauses `queue` whenever `cron` matches, for `duration_secs`. The window
+/// may be open right away, if it would have opened less than that ago.
+pub async fn add(
+    pg_pool: &PgPool,
+    queue: &str,
+    cron: &Cron,
+    duration_secs: f64,
+) -> Result<Window, sqlx::Error> {
+    let opens_at = current_or_next(cron, now(pg_pool).await?, duration_secs);
+    sqlx::query_as!(
+        Window,
+        r#"
+        INSERT INTO maintenance_windows (queue, cron, duration_secs, opens_at, closes_at)
+        VALUES ($1, $2, $3, to_timestamp($4), to_timestamp($4) + make_interval(secs => $3))
+        RETURNING id, queue, cron, duration_secs, opens_at::TEXT AS "opens_at!", closes_at::TEXT AS "closes_at!",
+                  now() >= opens_at AS "open!"
+        "#,
+        queue,
+        cron.to_string(),
+        duration_secs,
+        opens_at,
+    )
+    .fetch_one(pg_pool)
+    .await
+}
+
+/// Returns whether there was such a window.
+pub async fn remove(pg_pool: &PgPool, id: i64) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query!("DELETE FROM maintenance_windows WHERE id = $1", id)
+        .execute(pg_pool)
+        .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+/// Every window, by opening time.
+pub async fn list(pg_pool: &PgPool) -> Result<Vec<Window>, sqlx::Error> {
+    sqlx::query_as!(
+        Window,
+        r#"
+        SELECT id, queue, cron, duration_secs, opens_at::TEXT AS "opens_at!", closes_at::TEXT AS "closes_at!",
+               now() >= opens_at AND now() < closes_at AS "open!"
+        FROM maintenance_windows
+        ORDER BY opens_at, id
+        "#
+    )
+    .fetch_all(pg_pool)
+    .await
+}
+
+/// Moves the windows that closed on to their next occurrence, and returns
+/// them. Until then, they don't open again: this is what the scheduler runs.
+pub async fn advance(pg_pool: &PgPool) -> Result<Vec<Window>, sqlx::Error> {
+    let closed = sqlx::query!(
+        r#"
+        SELECT id, cron, duration_secs, EXTRACT(EPOCH FROM now())::FLOAT8 AS "now!"
+        FROM maintenance_windows
+        WHERE closes_at <= now()
+        ORDER BY id
+        "#
+    )
+    .fetch_all(pg_pool)
+    .await?;
+
+    let mut advanced = vec![];
+    for window in closed {
+        let cron: Cron = match window.cron.parse() {
+            Ok(cron) => cron,
+            Err(err) => {
+                println!(
+                    "Window #{} has an invalid cron expression ({}), left closed",
+                    window.id, err
+                );
+                continue;
+            }
+        };
+        let opens_at = current_or_next(&cron, window.now, window.duration_secs);
+        // Another scheduler may have moved it already.
+        let moved = sqlx::query_as!(
+            Window,
+            r#"
+            UPDATE maintenance_windows
+            SET opens_at = to_timestamp($2),
+                closes_at = to_timestamp($2) + make_interval(secs => duration_secs)
+            WHERE id = $1
+              AND closes_at <= now()
+            RETURNING id, queue, cron, duration_secs, opens_at::TEXT AS "opens_at!", closes_at::TEXT AS "closes_at!",
+                      now() >= opens_at AS "open!"
+            "#,
+            window.id,
+            opens_at,
+        )
+        .fetch_optional(pg_pool)
+        .await?;
+        advanced.extend(moved);
+    }
+    Ok(advanced)
+}
@@ -0,0 +1,145 @@
+use std::hint::black_box;
+use std::time::Duration;
+use std::time::Instant;
+
+use sqlx::postgres::PgRow;
+use sqlx::FromRow;
+use sqlx::PgPool;
+use sqlx::Row;
+
+use crate::JobRow;
+
+/// The columns the mappers expect, `Manual` relying on their order.
+pub const JOB_COLUMNS: &str =
+    "id, status, payload, params, tags, metadata, correlation_id, parent_job_id, checkpoint, attempts, max_attempts, retry_backoff_secs, timeout_secs";
+
+/// Turns a row selected with `JOB_COLUMNS` into a `JobRow`.
+pub trait RowMapper {
+    const NAME: &'static str;
+
+    fn map(row: &PgRow) -> Result<JobRow, sqlx::Error>;
+}
+
+/// What `#[derive(sqlx::FromRow)]` generates: each column is looked up by name.
+pub struct Derived;
+
+impl RowMapper for Derived {
+    const NAME: &'static str = "derived";
+
+    fn map(row: &PgRow) -> Result<JobRow, sqlx::Error> {
+        JobRow::from_row(row)
+    }
+}
+
+/// Written by hand, reading each column by position: no name lookup, but a
+/// reordered `SELECT` silently breaks it (a type mismatch is still caught).
+pub struct Manual;
+
+impl RowMapper for Manual {
+    const NAME: &'static str = "manual";
+
+    fn map(row: &PgRow) -> Result<JobRow, sqlx::Error> {
+        Ok(JobRow {
+            id: row.try_get(0)?,
+            status: row.try_get(1)?,
+            payload: row.try_get(2)?,
+            params: row.try_get(3)?,
+            tags: row.try_get(4)?,
+            metadata: row.try_get(5)?,
+            correlation_id: row.try_get(6)?,
+            parent_job_id: row.try_get(7)?,
+            checkpoint: row.try_get(8)?,
+            attempts: row.try_get(9)?,
+            max_attempts: row.try_get(10)?,
+            retry_backoff_secs: row.try_get(11)?,
+            timeout_secs: row.try_get(12)?,
+        })
+    }
+}
+
+/// Generates `count` rows shaped like the jobs table, every nullable column
+/// being set on half of them, so that results don't depend on what the
+/// database holds.
+pub async fn sample_rows(pg_pool: &PgPool, count: i64) -> Result<Vec<PgRow>, sqlx::Error> {
+    let sql = format!(
+        r#"
+        SELECT {}
+        FROM (
+            SELECT id,
+                   'Queued'::JOB_STATUS AS status,
+                   jsonb_build_object('type', 'SendEmail', 'email', 'user' || id || '@example.com') AS payload,
+                   CASE WHEN id % 2 = 0 THEN '{{"type": "FollowUp", "data": true}}'::JSONB END AS params,
+                   ARRAY['bench', 'user:' || id] AS tags,
+                   '{{"enqueuer": "bench"}}'::JSONB AS metadata,
+                   CASE WHEN id % 2 = 0 THEN 'req-' || id END AS correlation_id,
+                   CASE WHEN id % 2 = 0 THEN id - 1 END AS parent_job_id,
+                   CASE WHEN id % 2 = 0 THEN jsonb_build_object('sent', id) END AS checkpoint,
+                   (id % 5)::INTEGER AS attempts,
+                   CASE WHEN id % 2 = 0 THEN 3 END AS max_attempts,
+                   CASE WHEN id % 2 = 0 THEN 2.5::FLOAT8 END AS retry_backoff_secs,
+                   CASE WHEN id % 2 = 0 THEN 60::FLOAT8 END AS timeout_secs
+            FROM generate_series(1::BIGINT, $1) AS id
+        ) generated
+        "#,
+        JOB_COLUMNS
+    );
+    sqlx::query(&sql).bind(count).fetch_all(pg_pool).await
+}
+
+/// Maps every row with both mappers, and reports the first one they disagree on.
+pub fn compare(rows: &[PgRow]) -> Result<(), String> {
+    for (index, row) in rows.iter().enumerate() {
+        let derived = Derived::map(row).map_err(|err| format!("{}: {}", Derived::NAME, err))?;
+        let manual = Manual::map(row).map_err(|err| format!("{}: {}", Manual::NAME, err))?;
+        let (derived, manual) = (format!("{:?}", derived), format!("{:?}", manual));
+        if derived != manual {
+            return Err(format!(
+                "row {} differs:\n  {}: {}\n  {}: {}",
+                index,
+                Derived::NAME,
+                derived,
+                Manual::NAME,
+                manual
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// How long mapping every row takes, on average over `iterations` passes.
+pub fn time<M: RowMapper>(rows: &[PgRow], iterations: u32) -> Duration {
+    let started = Instant::now();
+    for _ in 0..iterations {
+        for row in rows {
+            black_box(M::map(black_box(row)).expect("rows were compared beforehand"));
+        }
+    }
+    started.elapsed() / iterations
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn mappers_agree_on_sample_rows() {
+        let pg_pool = crate::must_get_pool().await;
+        let rows = sample_rows(&pg_pool, 10).await.unwrap();
+        assert_eq!(rows.len(), 10);
+        assert_eq!(compare(&rows), Ok(()));
+
+        // Both sides of every nullable column are covered.
+        let (odd, even) = (
+            Manual::map(&rows[0]).unwrap(),
+            Manual::map(&rows[1]).unwrap(),
+        );
+        assert_eq!(
+            (odd.id, odd.params.is_none(), odd.timeout_secs),
+            (1, true, None)
+        );
+        assert_eq!(
+            (even.id, even.params.is_some(), even.timeout_secs),
+            (2, true, Some(60.0))
+        );
+    }
+}
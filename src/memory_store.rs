@@ -0,0 +1,722 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use serde_json::Value;
+use sqlx::types::Json;
+use tokio::time::Instant;
+
+use crate::blob;
+use crate::blob::Attachment;
+use crate::error::JobError;
+use crate::plugin;
+use crate::store::JobStore;
+use crate::store::QueueShares;
+use crate::store::LEASE_SECS;
+use crate::worker::MAX_ATTEMPTS;
+use crate::JobRow;
+use crate::JobStatus;
+use crate::Params;
+use crate::Payload;
+
+struct MemoryJob {
+    id: i64,
+    status: JobStatus,
+    payload: Payload,
+    params: Option<Params>,
+    correlation_id: Option<String>,
+    parent_job_id: Option<i64>,
+    checkpoint: Option<serde_json::Value>,
+    attempts: i32,
+    priority: i32,
+    run_at: Instant,
+    locked_until: Option<Instant>,
+}
+
+impl MemoryJob {
+    fn to_row(&self) -> JobRow {
+        JobRow {
+            id: self.id,
+            status: self.status,
+            payload: Json(self.payload.clone()),
+            params: self.params.clone().map(Json),
+            tags: vec![],
+            metadata: serde_json::json!({}),
+            correlation_id: self.correlation_id.clone(),
+            parent_job_id: self.parent_job_id,
+            checkpoint: self.checkpoint.clone(),
+            attempts: self.attempts,
+            max_attempts: None,
+            retry_backoff_secs: None,
+            timeout_secs: None,
+        }
+    }
+}
+
+/// A `JobStore` living in memory, with the same claim and lease semantics as
+/// the Postgres one, to run the worker without a database: due jobs are
+/// claimed by priority then id, leased, reaped once their lease ran out, and
+/// failed jobs come back after their retry delay. Time is tokio's, which
+/// tests can pause and advance.
+///
+/// What it leaves out: workflows (finishing a job only records its status),
+/// error messages, logs, queues with their settings, weights and limits,
+/// concurrency keys, circuit breakers and maintenance windows.
+#[derive(Default)]
+pub struct MemoryJobStore {
+    jobs: Mutex<Vec<MemoryJob>>,
+    effects: Mutex<HashMap<(i64, String), Value>>,
+    blobs: Mutex<Vec<Vec<u8>>>,
+}
+
+fn lease_end() -> Instant {
+    Instant::now() + Duration::from_secs_f64(LEASE_SECS)
+}
+
+/// The job, when still `Running` under `attempt`.
+fn leased(
+    jobs: &mut [MemoryJob],
+    job_id: i64,
+    attempt: i32,
+) -> Result<Option<&mut MemoryJob>, sqlx::Error> {
+    let job = jobs
+        .iter_mut()
+        .find(|job| job.id == job_id)
+        .ok_or(sqlx::Error::RowNotFound)?;
+    Ok((job.status == JobStatus::Running && job.attempts == attempt).then_some(job))
+}
+
+impl MemoryJobStore {
+    pub fn enqueue(&self, payload: Payload, params: Option<Params>) -> i64 {
+        self.enqueue_with_priority(payload, params, 0)
+    }
+
+    /// Higher priorities are claimed first.
+    pub fn enqueue_with_priority(
+        &self,
+        payload: Payload,
+        params: Option<Params>,
+        priority: i32,
+    ) -> i64 {
+        self.insert(payload, params, None, None, priority)
+    }
+
+    fn insert(
+        &self,
+        payload: Payload,
+        params: Option<Params>,
+        correlation_id: Option<String>,
+        parent_job_id: Option<i64>,
+        priority: i32,
+    ) -> i64 {
+        let mut jobs = self.jobs.lock().unwrap();
+        let id = jobs.len() as i64 + 1;
+        jobs.push(MemoryJob {
+            id,
+            status: JobStatus::Queued,
+            payload,
+            params,
+            correlation_id,
+            parent_job_id,
+            checkpoint: None,
+            attempts: 0,
+            priority,
+            run_at: Instant::now(),
+            locked_until: None,
+        });
+        id
+    }
+
+    /// Stores `content`, and returns how a job refers to it.
+    pub fn put_blob(&self, filename: &str, content: &[u8]) -> Attachment {
+        let mut blobs = self.blobs.lock().unwrap();
+        blobs.push(content.to_vec());
+        Attachment {
+            blob_id: blobs.len() as i64,
+            filename: filename.to_string(),
+            sha256: blob::sha256(content),
+        }
+    }
+
+    /// Every job, ordered by id.
+    pub fn rows(&self) -> Vec<JobRow> {
+        let jobs = self.jobs.lock().unwrap();
+        jobs.iter().map(MemoryJob::to_row).collect()
+    }
+}
+
+impl JobStore for MemoryJobStore {
+    async fn claim(
+        &self,
+        batch_size: i64,
+        job_types: &[String],
+        min_priority: Option<i32>,
+        _queues: &QueueShares,
+    ) -> Result<Vec<JobRow>, sqlx::Error> {
+        let mut jobs = self.jobs.lock().unwrap();
+        let now = Instant::now();
+        let mut due: Vec<&mut MemoryJob> = jobs
+            .iter_mut()
+            .filter(|job| job.status == JobStatus::Queued && job.run_at <= now)
+            .filter(|job| min_priority.is_none_or(|min_priority| job.priority >= min_priority))
+            .filter(|job| {
+                let payload_type = serde_json::json!(job.payload)["type"].clone();
+                if job_types.is_empty() {
+                    plugin::ENABLED || payload_type != "Wasm"
+                } else {
+                    job_types
+                        .iter()
+                        .any(|job_type| payload_type == job_type.as_str())
+                }
+            })
+            .collect();
+        due.sort_by_key(|job| (std::cmp::Reverse(job.priority), job.id));
+
+        let locked_until = lease_end();
+        Ok(due
+            .into_iter()
+            .take(batch_size.max(0) as usize)
+            .map(|job| {
+                job.status = JobStatus::Running;
+                job.attempts += 1;
+                job.locked_until = Some(locked_until);
+                job.to_row()
+            })
+            .collect())
+    }
+
+    async fn reap(&self) -> Result<u64, sqlx::Error> {
+        let mut jobs = self.jobs.lock().unwrap();
+        let now = Instant::now();
+        let mut reaped = 0;
+        for job in jobs.iter_mut() {
+            if job.status == JobStatus::Running && job.locked_until.is_some_and(|until| until < now)
+            {
+                job.status = if job.attempts >= MAX_ATTEMPTS {
+                    JobStatus::Failed
+                } else {
+                    JobStatus::Queued
+                };
+                job.locked_until = None;
+                reaped += 1;
+            }
+        }
+        Ok(reaped)
+    }
+
+    async fn release(&self, released: &[(i64, i32)], started: bool) -> Result<(), sqlx::Error> {
+        let mut jobs = self.jobs.lock().unwrap();
+        for &(job_id, attempt) in released {
+            let job = match leased(&mut jobs, job_id, attempt)? {
+                Some(job) => job,
+                None => continue,
+            };
+            job.status = JobStatus::Queued;
+            job.locked_until = None;
+            if !started {
+                job.attempts -= 1;
+            }
+        }
+        Ok(())
+    }
+
+    async fn finish(
+        &self,
+        job_id: i64,
+        attempt: i32,
+        status: JobStatus,
+    ) -> Result<bool, sqlx::Error> {
+        let mut jobs = self.jobs.lock().unwrap();
+        let job = match leased(&mut jobs, job_id, attempt)? {
+            Some(job) => job,
+            None => return Ok(false),
+        };
+        job.status = status;
+        job.locked_until = None;
+        Ok(true)
+    }
+
+    async fn fail(
+        &self,
+        job_id: i64,
+        attempt: i32,
+        _error: &JobError,
+        retry_in: Option<f64>,
+    ) -> Result<bool, sqlx::Error> {
+        let mut jobs = self.jobs.lock().unwrap();
+        let job = match leased(&mut jobs, job_id, attempt)? {
+            Some(job) => job,
+            None => return Ok(false),
+        };
+        job.locked_until = None;
+        match retry_in {
+            Some(retry_in) => {
+                job.status = JobStatus::Queued;
+                job.run_at = Instant::now() + Duration::from_secs_f64(retry_in);
+            }
+            None => job.status = JobStatus::Failed,
+        }
+        Ok(true)
+    }
+
+    async fn checkpoint(
+        &self,
+        job_id: i64,
+        attempt: i32,
+        state: serde_json::Value,
+    ) -> Result<bool, sqlx::Error> {
+        let mut jobs = self.jobs.lock().unwrap();
+        let job = match leased(&mut jobs, job_id, attempt)? {
+            Some(job) => job,
+            None => return Ok(false),
+        };
+        job.checkpoint = Some(state);
+        job.locked_until = Some(lease_end());
+        Ok(true)
+    }
+
+    async fn enqueue_child(
+        &self,
+        parent_job_id: i64,
+        correlation_id: Option<&str>,
+        payload: &Payload,
+        params: Option<&Params>,
+    ) -> Result<i64, sqlx::Error> {
+        let priority = {
+            let jobs = self.jobs.lock().unwrap();
+            jobs.iter()
+                .find(|job| job.id == parent_job_id)
+                .ok_or(sqlx::Error::RowNotFound)?
+                .priority
+        };
+        Ok(self.insert(
+            payload.clone(),
+            params.cloned(),
+            correlation_id.map(String::from),
+            Some(parent_job_id),
+            priority,
+        ))
+    }
+
+    async fn effect(&self, job_id: i64, key: &str) -> Result<Option<Value>, sqlx::Error> {
+        let effects = self.effects.lock().unwrap();
+        Ok(effects.get(&(job_id, key.to_string())).cloned())
+    }
+
+    async fn record_effect(
+        &self,
+        job_id: i64,
+        key: &str,
+        result: Value,
+    ) -> Result<(), sqlx::Error> {
+        let mut effects = self.effects.lock().unwrap();
+        effects.entry((job_id, key.to_string())).or_insert(result);
+        Ok(())
+    }
+
+    async fn blob(&self, blob_id: i64) -> Result<Option<Vec<u8>>, sqlx::Error> {
+        let blobs = self.blobs.lock().unwrap();
+        let index = usize::try_from(blob_id - 1).ok();
+        Ok(index.and_then(|index| blobs.get(index)).cloned())
+    }
+
+    async fn record_logs(
+        &self,
+        _job_id: i64,
+        _attempt: i32,
+        _lines: &[String],
+    ) -> Result<(), sqlx::Error> {
+        Ok(())
+    }
+
+    async fn start(&self, job_id: i64, attempt: i32) -> Result<bool, sqlx::Error> {
+        let mut jobs = self.jobs.lock().unwrap();
+        let job = match leased(&mut jobs, job_id, attempt)? {
+            Some(job) => job,
+            None => return Ok(false),
+        };
+        job.locked_until = Some(lease_end());
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn state(store: &MemoryJobStore, job_id: i64) -> (JobStatus, i32) {
+        let row = store
+            .rows()
+            .into_iter()
+            .find(|row| row.id == job_id)
+            .expect("no such job");
+        (row.status, row.attempts)
+    }
+
+    fn ids(rows: &[JobRow]) -> Vec<i64> {
+        rows.iter().map(|row| row.id).collect()
+    }
+
+    fn email() -> Payload {
+        Payload::SendEmail {
+            email: "user@example.com".to_string(),
+            template: None,
+            attachments: vec![],
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn claim_leases_due_jobs_in_order() {
+        let store = MemoryJobStore::default();
+        let first = store.enqueue(Payload::NOOP, None);
+        let second = store.enqueue(email(), None);
+        let third = store.enqueue(Payload::NOOP, None);
+
+        let claimed = store
+            .claim(2, &[], None, &QueueShares::default())
+            .await
+            .unwrap();
+        assert_eq!(ids(&claimed), vec![first, second]);
+        assert_eq!(state(&store, first), (JobStatus::Running, 1));
+        assert_eq!(state(&store, third), (JobStatus::Queued, 0));
+
+        // Claimed jobs aren't claimed again.
+        let claimed = store
+            .claim(2, &[], None, &QueueShares::default())
+            .await
+            .unwrap();
+        assert_eq!(ids(&claimed), vec![third]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn claim_takes_higher_priorities_first() {
+        let store = MemoryJobStore::default();
+        let low = store.enqueue_with_priority(Payload::NOOP, None, -1);
+        let normal = store.enqueue(Payload::NOOP, None);
+        let urgent = store.enqueue_with_priority(Payload::NOOP, None, 10);
+        let also_urgent = store.enqueue_with_priority(Payload::NOOP, None, 10);
+
+        let claimed = store
+            .claim(3, &[], None, &QueueShares::default())
+            .await
+            .unwrap();
+        assert_eq!(ids(&claimed), vec![urgent, also_urgent, normal]);
+        let claimed = store
+            .claim(3, &[], None, &QueueShares::default())
+            .await
+            .unwrap();
+        assert_eq!(ids(&claimed), vec![low]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn claim_skips_jobs_below_the_minimum_priority() {
+        let store = MemoryJobStore::default();
+        store.enqueue(Payload::NOOP, None);
+        let urgent = store.enqueue_with_priority(Payload::NOOP, None, 10);
+
+        let claimed = store
+            .claim(5, &[], Some(5), &QueueShares::default())
+            .await
+            .unwrap();
+        assert_eq!(ids(&claimed), vec![urgent]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn children_inherit_the_priority_of_their_parent() {
+        let store = MemoryJobStore::default();
+        store.enqueue(Payload::NOOP, None);
+        let parent = store.enqueue_with_priority(Payload::NOOP, None, 10);
+        let child = store
+            .enqueue_child(parent, None, &Payload::NOOP, None)
+            .await
+            .unwrap();
+
+        let claimed = store
+            .claim(1, &[], Some(10), &QueueShares::default())
+            .await
+            .unwrap();
+        assert_eq!(ids(&claimed), vec![parent]);
+        let claimed = store
+            .claim(1, &[], Some(10), &QueueShares::default())
+            .await
+            .unwrap();
+        assert_eq!(ids(&claimed), vec![child]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn claim_only_takes_the_given_types() {
+        let store = MemoryJobStore::default();
+        store.enqueue(Payload::NOOP, None);
+        let email = store.enqueue(email(), None);
+
+        let claimed = store
+            .claim(5, &["SendEmail".to_string()], None, &QueueShares::default())
+            .await
+            .unwrap();
+        assert_eq!(ids(&claimed), vec![email]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn wasm_jobs_are_left_to_workers_that_run_plugins() {
+        let store = MemoryJobStore::default();
+        let wasm = store.enqueue(
+            Payload::Wasm {
+                module: "upper".to_string(),
+                input: serde_json::json!("hello"),
+            },
+            None,
+        );
+        let noop = store.enqueue(Payload::NOOP, None);
+
+        let claimed = store
+            .claim(5, &[], None, &QueueShares::default())
+            .await
+            .unwrap();
+        if plugin::ENABLED {
+            assert_eq!(ids(&claimed), vec![wasm, noop]);
+        } else {
+            assert_eq!(ids(&claimed), vec![noop]);
+            // Asked for by type, they are claimed all the same.
+            let claimed = store
+                .claim(5, &["Wasm".to_string()], None, &QueueShares::default())
+                .await
+                .unwrap();
+            assert_eq!(ids(&claimed), vec![wasm]);
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn reap_requeues_expired_leases_only() {
+        let store = MemoryJobStore::default();
+        let job = store.enqueue(Payload::NOOP, None);
+        store
+            .claim(1, &[], None, &QueueShares::default())
+            .await
+            .unwrap();
+
+        tokio::time::advance(Duration::from_secs_f64(LEASE_SECS - 1.0)).await;
+        assert_eq!(store.reap().await.unwrap(), 0);
+        assert_eq!(state(&store, job), (JobStatus::Running, 1));
+
+        tokio::time::advance(Duration::from_secs(2)).await;
+        assert_eq!(store.reap().await.unwrap(), 1);
+        assert_eq!(state(&store, job), (JobStatus::Queued, 1));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn reaped_job_is_claimed_again_as_a_retry() {
+        let store = MemoryJobStore::default();
+        let job = store.enqueue(Payload::NOOP, None);
+        store
+            .claim(1, &[], None, &QueueShares::default())
+            .await
+            .unwrap();
+        tokio::time::advance(Duration::from_secs_f64(LEASE_SECS + 1.0)).await;
+        store.reap().await.unwrap();
+
+        let claimed = store
+            .claim(1, &[], None, &QueueShares::default())
+            .await
+            .unwrap();
+        assert_eq!(ids(&claimed), vec![job]);
+        assert_eq!(claimed[0].attempts, 2);
+        assert_eq!(state(&store, job), (JobStatus::Running, 2));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn reaping_fails_jobs_out_of_attempts() {
+        let store = MemoryJobStore::default();
+        let job = store.enqueue(Payload::NOOP, None);
+        for attempt in 1..=MAX_ATTEMPTS {
+            assert_eq!(
+                store
+                    .claim(1, &[], None, &QueueShares::default())
+                    .await
+                    .unwrap()
+                    .len(),
+                1
+            );
+            tokio::time::advance(Duration::from_secs_f64(LEASE_SECS + 1.0)).await;
+            assert_eq!(store.reap().await.unwrap(), 1);
+            let expected = if attempt < MAX_ATTEMPTS {
+                JobStatus::Queued
+            } else {
+                JobStatus::Failed
+            };
+            assert_eq!(state(&store, job), (expected, attempt));
+        }
+        assert!(store
+            .claim(1, &[], None, &QueueShares::default())
+            .await
+            .unwrap()
+            .is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn outcome_of_a_lost_lease_is_not_recorded() {
+        let store = MemoryJobStore::default();
+        let job = store.enqueue(Payload::NOOP, None);
+        store
+            .claim(1, &[], None, &QueueShares::default())
+            .await
+            .unwrap();
+        tokio::time::advance(Duration::from_secs_f64(LEASE_SECS + 1.0)).await;
+        store.reap().await.unwrap();
+        store
+            .claim(1, &[], None, &QueueShares::default())
+            .await
+            .unwrap();
+
+        // The first attempt's worker comes back late.
+        assert!(!store.finish(job, 1, JobStatus::Done).await.unwrap());
+        let error = JobError::retryable("flaky");
+        assert!(!store.fail(job, 1, &error, None).await.unwrap());
+        assert_eq!(state(&store, job), (JobStatus::Running, 2));
+
+        assert!(store.finish(job, 2, JobStatus::Done).await.unwrap());
+        // Finishing again changes nothing.
+        assert!(!store.finish(job, 2, JobStatus::Failed).await.unwrap());
+        assert_eq!(state(&store, job), (JobStatus::Done, 2));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn checkpoint_renews_the_lease() {
+        let store = MemoryJobStore::default();
+        let job = store.enqueue(Payload::NOOP, None);
+        store
+            .claim(1, &[], None, &QueueShares::default())
+            .await
+            .unwrap();
+
+        tokio::time::advance(Duration::from_secs_f64(LEASE_SECS - 1.0)).await;
+        assert!(store
+            .checkpoint(job, 1, serde_json::json!({ "sent": 1 }))
+            .await
+            .unwrap());
+        tokio::time::advance(Duration::from_secs(2)).await;
+        assert_eq!(store.reap().await.unwrap(), 0);
+        assert_eq!(state(&store, job), (JobStatus::Running, 1));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn lost_lease_is_neither_started_nor_checkpointed() {
+        let store = MemoryJobStore::default();
+        let job = store.enqueue(Payload::NOOP, None);
+        store
+            .claim(1, &[], None, &QueueShares::default())
+            .await
+            .unwrap();
+        tokio::time::advance(Duration::from_secs_f64(LEASE_SECS + 1.0)).await;
+        store.reap().await.unwrap();
+        store
+            .claim(1, &[], None, &QueueShares::default())
+            .await
+            .unwrap();
+
+        // The first attempt's worker gets to the job late.
+        assert!(!store.start(job, 1).await.unwrap());
+        let stale = serde_json::json!({ "sent": 1 });
+        assert!(!store.checkpoint(job, 1, stale).await.unwrap());
+        assert_eq!(store.rows()[0].checkpoint, None);
+
+        // Starting renews the lease of the current attempt.
+        tokio::time::advance(Duration::from_secs_f64(LEASE_SECS - 1.0)).await;
+        assert!(store.start(job, 2).await.unwrap());
+        tokio::time::advance(Duration::from_secs(2)).await;
+        assert_eq!(store.reap().await.unwrap(), 0);
+        assert_eq!(state(&store, job), (JobStatus::Running, 2));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn release_only_counts_started_jobs_as_attempts() {
+        let store = MemoryJobStore::default();
+        let started = store.enqueue(Payload::NOOP, None);
+        let unstarted = store.enqueue(Payload::NOOP, None);
+        store
+            .claim(2, &[], None, &QueueShares::default())
+            .await
+            .unwrap();
+
+        store.release(&[(started, 1)], true).await.unwrap();
+        store.release(&[(unstarted, 1)], false).await.unwrap();
+        assert_eq!(state(&store, started), (JobStatus::Queued, 1));
+        assert_eq!(state(&store, unstarted), (JobStatus::Queued, 0));
+
+        // Releasing again, as a replay would, changes nothing.
+        store.release(&[(unstarted, 1)], false).await.unwrap();
+        assert_eq!(state(&store, unstarted), (JobStatus::Queued, 0));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn release_leaves_a_job_claimed_again_alone() {
+        let store = MemoryJobStore::default();
+        let job = store.enqueue(Payload::NOOP, None);
+        store
+            .claim(1, &[], None, &QueueShares::default())
+            .await
+            .unwrap();
+        tokio::time::advance(Duration::from_secs_f64(LEASE_SECS + 1.0)).await;
+        store.reap().await.unwrap();
+        store
+            .claim(1, &[], None, &QueueShares::default())
+            .await
+            .unwrap();
+
+        // The first attempt's worker is stopped, late.
+        store.release(&[(job, 1)], true).await.unwrap();
+        assert_eq!(state(&store, job), (JobStatus::Running, 2));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn failed_job_comes_back_after_its_retry_delay() {
+        let store = MemoryJobStore::default();
+        let job = store.enqueue(Payload::NOOP, None);
+        store
+            .claim(1, &[], None, &QueueShares::default())
+            .await
+            .unwrap();
+        store
+            .fail(job, 1, &JobError::retryable("flaky"), Some(10.0))
+            .await
+            .unwrap();
+        assert_eq!(state(&store, job), (JobStatus::Queued, 1));
+
+        tokio::time::advance(Duration::from_secs(9)).await;
+        assert!(store
+            .claim(1, &[], None, &QueueShares::default())
+            .await
+            .unwrap()
+            .is_empty());
+
+        tokio::time::advance(Duration::from_secs(1)).await;
+        let claimed = store
+            .claim(1, &[], None, &QueueShares::default())
+            .await
+            .unwrap();
+        assert_eq!(ids(&claimed), vec![job]);
+        assert_eq!(state(&store, job), (JobStatus::Running, 2));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn failed_job_without_retry_is_never_claimed_again() {
+        let store = MemoryJobStore::default();
+        let job = store.enqueue(Payload::NOOP, None);
+        store
+            .claim(1, &[], None, &QueueShares::default())
+            .await
+            .unwrap();
+        store
+            .fail(job, 1, &JobError::permanent("bad payload"), None)
+            .await
+            .unwrap();
+
+        assert_eq!(state(&store, job), (JobStatus::Failed, 1));
+        tokio::time::advance(Duration::from_secs_f64(LEASE_SECS * 2.0)).await;
+        assert_eq!(store.reap().await.unwrap(), 0);
+        assert!(store
+            .claim(1, &[], None, &QueueShares::default())
+            .await
+            .unwrap()
+            .is_empty());
+    }
+}
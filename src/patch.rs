@@ -0,0 +1,164 @@
+use std::fmt;
+
+use serde::Deserialize;
+use serde::Serialize;
+use serde_json::Value;
+use sqlx::PgPool;
+
+use crate::JobStatus;
+use crate::Payload;
+
+/// What to change about a queued job, `None` leaving it as is.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct JobPatch {
+    pub priority: Option<i32>,
+    /// Anything Postgres reads as a `timestamptz`, e.g.
+    /// `2026-10-16 09:00:00+00`.
+    pub run_at: Option<String>,
+    /// The job keeps the options it was enqueued with, whatever the settings
+    /// of its new queue.
+    pub queue: Option<String>,
+    /// In any format a producer may insert: it is stored internally tagged,
+    /// and must then read as a `Payload`.
+    pub payload: Option<Value>,
+}
+
+impl JobPatch {
+    pub fn is_empty(&self) -> bool {
+        self.priority.is_none()
+            && self.run_at.is_none()
+            && self.queue.is_none()
+            && self.payload.is_none()
+    }
+}
+
+/// The job once updated.
+#[derive(Serialize)]
+pub struct Updated {
+    pub id: i64,
+    pub priority: i32,
+    pub run_at: String,
+    pub queue: String,
+    pub payload: Value,
+}
+
+pub enum UpdateError {
+    NoSuchJob,
+    /// Claimed, or done with: only queued jobs can still be changed.
+    NotQueued(JobStatus),
+    /// Draining queues take no new jobs, moved ones included.
+    Draining(String),
+    /// An earlier attempt checkpointed or recorded effects, which are about
+    /// the payload it ran with.
+    PayloadInUse,
+    /// A value the database rejected, e.g. a `run_at` it can't read.
+    Invalid(String),
+    Database(sqlx::Error),
+}
+
+impl From<sqlx::Error> for UpdateError {
+    fn from(err: sqlx::Error) -> Self {
+        match &err {
+            // The data_exception class.
+            sqlx::Error::Database(db_err)
+                if db_err.code().is_some_and(|code| code.starts_with("22")) =>
+            {
+                UpdateError::Invalid(db_err.message().to_string())
+            }
+            _ => UpdateError::Database(err),
+        }
+    }
+}
+
+impl fmt::Display for UpdateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            UpdateError::NoSuchJob => write!(f, "no such job"),
+            UpdateError::NotQueued(status) => {
+                write!(
+                    f,
+                    "the job is {:?}, only queued jobs can be updated",
+                    status
+                )
+            }
+            UpdateError::Draining(queue) => {
+                write!(f, "queue \"{}\" is draining, it takes no new jobs", queue)
+            }
+            UpdateError::PayloadInUse => write!(
+                f,
+                "an earlier attempt made progress on the current payload, enqueue a new job instead"
+            ),
+            UpdateError::Invalid(message) => write!(f, "{}", message),
+            UpdateError::Database(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+/// Applies `patch` to a job that is still `Queued`, all at once or not at
+/// all. The job's row is locked first: a worker claiming it meanwhile either
+/// got there first, and the update is turned down, or skips it.
+pub async fn update_job(
+    pg_pool: &PgPool,
+    id: i64,
+    patch: &JobPatch,
+) -> Result<Updated, UpdateError> {
+    let mut tx = pg_pool.begin().await?;
+    let job = sqlx::query!(
+        r#"
+        SELECT status AS "status: JobStatus", queue,
+               checkpoint IS NOT NULL OR EXISTS (SELECT 1 FROM effects WHERE job_id = jobs.id) AS "made_progress!"
+        FROM jobs
+        WHERE id = $1
+        FOR UPDATE
+        "#,
+        id,
+    )
+    .fetch_optional(&mut tx)
+    .await?
+    .ok_or(UpdateError::NoSuchJob)?;
+
+    if job.status != JobStatus::Queued {
+        return Err(UpdateError::NotQueued(job.status));
+    }
+    if patch.payload.is_some() && job.made_progress {
+        return Err(UpdateError::PayloadInUse);
+    }
+    if let Some(queue) = patch.queue.as_deref().filter(|queue| *queue != job.queue) {
+        let draining = sqlx::query_scalar!(
+            r#"SELECT EXISTS (SELECT 1 FROM queues WHERE name = $1 AND draining) AS "draining!""#,
+            queue,
+        )
+        .fetch_one(&mut tx)
+        .await?;
+        if draining {
+            return Err(UpdateError::Draining(queue.to_string()));
+        }
+    }
+
+    let updated = sqlx::query_as!(
+        Updated,
+        r#"
+        UPDATE jobs
+        SET priority = COALESCE($2, priority),
+            run_at = COALESCE($3::TEXT::TIMESTAMPTZ, run_at),
+            queue = COALESCE($4, queue),
+            payload = COALESCE($5, payload)
+        WHERE id = $1
+        RETURNING id, priority, run_at::TEXT AS "run_at!", queue, payload
+        "#,
+        id,
+        patch.priority,
+        patch.run_at,
+        patch.queue,
+        patch.payload.as_ref(),
+    )
+    .fetch_one(&mut tx)
+    .await?;
+    // The trigger normalized the payload, what it stored is what workers get.
+    if let Err(err) = serde_json::from_value::<Payload>(updated.payload.clone()) {
+        return Err(UpdateError::Invalid(format!("invalid payload: {}", err)));
+    }
+    tx.commit().await?;
+    Ok(updated)
+}
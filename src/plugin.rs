@@ -0,0 +1,231 @@
+use crate::error::JobError;
+
+/// What a plugin run produced: its result (or error), and what it logged
+/// along the way.
+pub struct Outcome {
+    pub logs: Vec<String>,
+    pub result: Result<Vec<u8>, JobError>,
+}
+
+/// Whether this binary runs plugins. Without them, `Wasm` jobs are only
+/// claimed when asked for by type, so that they are left to the workers that
+/// can run them.
+pub const ENABLED: bool = cfg!(feature = "wasm");
+
+#[cfg(not(feature = "wasm"))]
+pub fn run(_module: &str, _input: &[u8]) -> Outcome {
+    Outcome {
+        logs: vec![],
+        // Another worker may have been built with it.
+        result: Err(JobError::retryable(
+            "this worker was built without the `wasm` feature",
+        )),
+    }
+}
+
+#[cfg(feature = "wasm")]
+pub use wasm::run;
+
+/// Plugins are WebAssembly modules implementing this interface:
+///
+/// - they export their `memory`, and `alloc(len: i32) -> i32` for the host to
+///   write the input to;
+/// - they export `run(ptr: i32, len: i32) -> i32`, called once with the
+///   input, returning 0 on success, 1 on a retryable error and anything else
+///   on a permanent one;
+/// - they may import `host.output(ptr: i32, len: i32)`, which sets the result
+///   (the error message on failure), and `host.log(ptr: i32, len: i32)`.
+///
+/// Each run gets a fresh instance, with limited fuel and memory.
+#[cfg(feature = "wasm")]
+mod wasm {
+    use std::collections::HashMap;
+    use std::path::Path;
+    use std::path::PathBuf;
+    use std::sync::Mutex;
+    use std::sync::OnceLock;
+    use std::time::SystemTime;
+
+    use wasmtime::Caller;
+    use wasmtime::Config;
+    use wasmtime::Engine;
+    use wasmtime::Extern;
+    use wasmtime::Linker;
+    use wasmtime::Module;
+    use wasmtime::Store;
+    use wasmtime::StoreLimits;
+    use wasmtime::StoreLimitsBuilder;
+    use wasmtime::Trap;
+
+    use super::Outcome;
+    use crate::error::JobError;
+
+    /// Where plugins are read from, relative to the worker's working
+    /// directory: the `upper` module is `plugins/upper.wasm`, or
+    /// `plugins/upper.wat` in the text format.
+    const PLUGINS_DIR: &str = "plugins";
+
+    /// Roughly one unit per instruction: a run can't loop forever.
+    const FUEL: u64 = 1_000_000_000;
+
+    const MAX_MEMORY_BYTES: usize = 64 * 1024 * 1024;
+
+    struct State {
+        limits: StoreLimits,
+        output: Vec<u8>,
+        logs: Vec<String>,
+    }
+
+    fn engine() -> &'static Engine {
+        static ENGINE: OnceLock<Engine> = OnceLock::new();
+        ENGINE.get_or_init(|| {
+            let mut config = Config::new();
+            config.consume_fuel(true);
+            Engine::new(&config).expect("invalid wasm engine configuration")
+        })
+    }
+
+    fn path(module: &str) -> Result<PathBuf, JobError> {
+        let valid = !module.is_empty()
+            && module
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+        if !valid {
+            return Err(JobError::permanent(format!(
+                "invalid plugin name: {:?}",
+                module
+            )));
+        }
+        ["wasm", "wat"]
+            .iter()
+            .map(|extension| Path::new(PLUGINS_DIR).join(format!("{}.{}", module, extension)))
+            .find(|path| path.exists())
+            // It may be deployed by the time the job is retried.
+            .ok_or_else(|| JobError::retryable(format!("no such plugin: {}", module)))
+    }
+
+    /// Compiled modules are kept until their file changes: deploying a new
+    /// version of a plugin doesn't need a restart.
+    fn load(path: &Path) -> Result<Module, JobError> {
+        static MODULES: OnceLock<Mutex<HashMap<PathBuf, (SystemTime, Module)>>> = OnceLock::new();
+
+        let modified = std::fs::metadata(path)
+            .and_then(|meta| meta.modified())
+            .map_err(|err| {
+                JobError::retryable(format!("cannot read {}: {}", path.display(), err))
+            })?;
+        let mut modules = MODULES
+            .get_or_init(Default::default)
+            .lock()
+            .expect("plugin cache poisoned");
+        if let Some((compiled_at, module)) = modules.get(path) {
+            if *compiled_at == modified {
+                return Ok(module.clone());
+            }
+        }
+
+        let module = Module::from_file(engine(), path).map_err(|err| {
+            JobError::permanent(format!("cannot compile {}: {:#}", path.display(), err))
+        })?;
+        modules.insert(path.to_path_buf(), (modified, module.clone()));
+        Ok(module)
+    }
+
+    /// Copies `len` bytes at `ptr` out of the plugin's memory. Bounds are
+    /// checked first: a plugin can't have the host allocate more than the
+    /// memory it was given.
+    fn read(caller: &mut Caller<'_, State>, ptr: i32, len: i32) -> wasmtime::Result<Vec<u8>> {
+        let memory = caller
+            .get_export("memory")
+            .and_then(Extern::into_memory)
+            .ok_or_else(|| wasmtime::Error::msg("plugin exports no memory"))?;
+        let bytes = usize::try_from(ptr)
+            .ok()
+            .zip(usize::try_from(len).ok())
+            .and_then(|(start, len)| memory.data(&caller).get(start..start.checked_add(len)?))
+            .ok_or_else(|| {
+                wasmtime::Error::msg(format!(
+                    "{} bytes at {} are out of the plugin's memory",
+                    len, ptr
+                ))
+            })?;
+        Ok(bytes.to_vec())
+    }
+
+    fn execute(module: &Module, input: &[u8], store: &mut Store<State>) -> wasmtime::Result<i32> {
+        let mut linker = Linker::new(engine());
+        linker.func_wrap(
+            "host",
+            "output",
+            |mut caller: Caller<'_, State>, ptr: i32, len: i32| {
+                caller.data_mut().output = read(&mut caller, ptr, len)?;
+                Ok(())
+            },
+        )?;
+        linker.func_wrap(
+            "host",
+            "log",
+            |mut caller: Caller<'_, State>, ptr: i32, len: i32| {
+                let message = String::from_utf8_lossy(&read(&mut caller, ptr, len)?).into_owned();
+                caller.data_mut().logs.push(message);
+                Ok(())
+            },
+        )?;
+
+        let instance = linker.instantiate(&mut *store, module)?;
+        let memory = instance
+            .get_memory(&mut *store, "memory")
+            .ok_or_else(|| wasmtime::Error::msg("plugin exports no memory"))?;
+        let alloc = instance.get_typed_func::<i32, i32>(&mut *store, "alloc")?;
+        let run = instance.get_typed_func::<(i32, i32), i32>(&mut *store, "run")?;
+
+        let len = i32::try_from(input.len())?;
+        let ptr = alloc.call(&mut *store, len)?;
+        memory.write(&mut *store, ptr as usize, input)?;
+        run.call(&mut *store, (ptr, len))
+    }
+
+    /// Runs the plugin's `run` on `input`. Blocks until it returns, or runs
+    /// out of fuel.
+    pub fn run(module: &str, input: &[u8]) -> Outcome {
+        let module = match path(module).and_then(|path| load(&path)) {
+            Ok(module) => module,
+            Err(err) => {
+                return Outcome {
+                    logs: vec![],
+                    result: Err(err),
+                }
+            }
+        };
+
+        let mut store = Store::new(
+            engine(),
+            State {
+                limits: StoreLimitsBuilder::new()
+                    .memory_size(MAX_MEMORY_BYTES)
+                    .build(),
+                output: vec![],
+                logs: vec![],
+            },
+        );
+        store.limiter(|state| &mut state.limits);
+        store.set_fuel(FUEL).expect("fuel is enabled");
+
+        let status = execute(&module, input, &mut store);
+        let State { output, logs, .. } = store.into_data();
+        let result = match status {
+            Ok(0) => Ok(output),
+            Ok(1) => Err(JobError::retryable(String::from_utf8_lossy(&output))),
+            Ok(_) => Err(JobError::permanent(String::from_utf8_lossy(&output))),
+            Err(err) if err.downcast_ref::<Trap>() == Some(&Trap::OutOfFuel) => Err(
+                JobError::timeout(format!("plugin ran out of fuel ({} units)", FUEL)),
+            ),
+            Err(err) => Err(JobError::permanent(match err.downcast_ref::<Trap>() {
+                Some(trap) => format!("plugin trapped: {}", trap),
+                // Host errors come wrapped in the plugin's backtrace.
+                None => format!("plugin failed: {}", err.root_cause()),
+            })),
+        };
+        Outcome { logs, result }
+    }
+}
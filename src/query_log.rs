@@ -0,0 +1,120 @@
+use std::future::Future;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+use std::time::Instant;
+
+use serde_json::Value;
+use sqlx::Execute;
+use sqlx::Postgres;
+
+/// Longer arguments (typically payloads) are cut, the point is to recognize
+/// them, not to read them in full.
+const MAX_ARG_LEN: usize = 120;
+
+/// Object keys whose values never make it to the logs, at any depth.
+const SECRET_KEYS: &[&str] = &["password", "secret", "token", "api_key", "authorization"];
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Has every `logged!` query print its SQL, bound arguments and timing, for
+/// development.
+pub fn enable() {
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+/// A query, along with the arguments bound to it, named after the variables
+/// they were bound from. Built by `logged!`.
+pub struct Logged<Q> {
+    pub query: Q,
+    pub args: Vec<(&'static str, Value)>,
+}
+
+impl<'q, Q: Execute<'q, Postgres>> Logged<Q> {
+    /// Runs the query with `run` (`execute`, `fetch_all`...), then prints it
+    /// when logging is enabled.
+    pub async fn run<T, Fut>(self, run: impl FnOnce(Q) -> Fut) -> Result<T, sqlx::Error>
+    where
+        Fut: Future<Output = Result<T, sqlx::Error>>,
+    {
+        if !ENABLED.load(Ordering::Relaxed) {
+            return run(self.query).await;
+        }
+
+        let sql = self.query.sql();
+        let started = Instant::now();
+        let result = run(self.query).await;
+        let elapsed = started.elapsed();
+
+        println!(
+            "[sql] {}",
+            sql.split_whitespace().collect::<Vec<_>>().join(" ")
+        );
+        for (index, (name, value)) in self.args.iter().enumerate() {
+            println!("[sql]   ${} {} = {}", index + 1, name, render(value));
+        }
+        match &result {
+            Ok(_) => println!("[sql]   ok in {:?}", elapsed),
+            Err(err) => println!("[sql]   failed in {:?}: {}", elapsed, err),
+        }
+        result
+    }
+}
+
+/// Wraps one of sqlx's query macros into a `Logged` query. The arguments must
+/// be variables (type overrides allowed): the values logged are then the very
+/// ones bound, and can't drift apart from them.
+///
+/// `logged!(query("..."))` wraps a query without arguments, checked at runtime.
+macro_rules! logged {
+    (query($sql:literal)) => {
+        $crate::query_log::Logged {
+            args: vec![],
+            query: sqlx::query($sql),
+        }
+    };
+    (query_as!($row:ident, $sql:literal $(, $arg:ident $(as $ty:ty)?)* $(,)?)) => {
+        $crate::query_log::Logged {
+            args: vec![$((stringify!($arg), serde_json::json!($arg))),*],
+            query: sqlx::query_as!($row, $sql $(, $arg $(as $ty)?)*),
+        }
+    };
+    ($macro:ident!($sql:literal $(, $arg:ident $(as $ty:ty)?)* $(,)?)) => {
+        $crate::query_log::Logged {
+            args: vec![$((stringify!($arg), serde_json::json!($arg))),*],
+            query: sqlx::$macro!($sql $(, $arg $(as $ty)?)*),
+        }
+    };
+}
+pub(crate) use logged;
+
+fn render(value: &Value) -> String {
+    let rendered = redact(value).to_string();
+    if rendered.chars().count() <= MAX_ARG_LEN {
+        return rendered;
+    }
+    let truncated: String = rendered.chars().take(MAX_ARG_LEN).collect();
+    format!("{}... ({} bytes)", truncated, rendered.len())
+}
+
+fn redact(value: &Value) -> Value {
+    match value {
+        Value::Object(object) => Value::Object(
+            object
+                .iter()
+                .map(|(key, value)| {
+                    let secret = SECRET_KEYS
+                        .iter()
+                        .any(|secret| key.to_ascii_lowercase().contains(secret));
+                    let value = if secret {
+                        Value::String("[redacted]".to_string())
+                    } else {
+                        redact(value)
+                    };
+                    (key.clone(), value)
+                })
+                .collect(),
+        ),
+        Value::Array(values) => Value::Array(values.iter().map(redact).collect()),
+        _ => value.clone(),
+    }
+}
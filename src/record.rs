@@ -0,0 +1,262 @@
+use serde_json::Value;
+use sqlx::postgres::PgRow;
+use sqlx::types::Json;
+use sqlx::FromRow;
+
+use crate::DomainJob;
+use crate::JobRow;
+use crate::JobStatus;
+
+/// The columns of a job as `query!` hands them over, JSON left undecoded.
+/// `query!` records are anonymous structs, no trait can be implemented for
+/// them: `job_record!` moves their fields into this named one instead.
+pub struct JobRecord {
+    pub id: i64,
+    pub status: JobStatus,
+    pub payload: Value,
+    pub params: Option<Value>,
+    pub tags: Vec<String>,
+    pub metadata: Value,
+    pub correlation_id: Option<String>,
+    pub parent_job_id: Option<i64>,
+    pub checkpoint: Option<Value>,
+    pub attempts: i32,
+    pub max_attempts: Option<i32>,
+    pub retry_backoff_secs: Option<f64>,
+    pub timeout_secs: Option<f64>,
+}
+
+/// Builds a `JobRecord` from a `query!` record selecting the same columns,
+/// matched by name: a missing or mistyped column fails to compile.
+macro_rules! job_record {
+    ($record:expr) => {{
+        let record = $record;
+        $crate::record::JobRecord {
+            id: record.id,
+            status: record.status,
+            payload: record.payload,
+            params: record.params,
+            tags: record.tags,
+            metadata: record.metadata,
+            correlation_id: record.correlation_id,
+            parent_job_id: record.parent_job_id,
+            checkpoint: record.checkpoint,
+            attempts: record.attempts,
+            max_attempts: record.max_attempts,
+            retry_backoff_secs: record.retry_backoff_secs,
+            timeout_secs: record.timeout_secs,
+        }
+    }};
+}
+pub(crate) use job_record;
+
+/// Converts what one of sqlx's query APIs returned. Every demo path goes
+/// through it, whether it got a `JobRow` already, a `query!` record or a raw
+/// `PgRow`, so that they all decode jobs the same way.
+pub trait FromRecord<R>: Sized {
+    fn from_record(record: R) -> Result<Self, sqlx::Error>;
+}
+
+fn decode_json<T: serde::de::DeserializeOwned>(value: Value) -> Result<T, sqlx::Error> {
+    serde_json::from_value(value).map_err(|err| sqlx::Error::Decode(err.into()))
+}
+
+impl FromRecord<JobRow> for JobRow {
+    fn from_record(record: JobRow) -> Result<Self, sqlx::Error> {
+        Ok(record)
+    }
+}
+
+impl FromRecord<JobRecord> for JobRow {
+    fn from_record(record: JobRecord) -> Result<Self, sqlx::Error> {
+        Ok(JobRow {
+            id: record.id,
+            status: record.status,
+            payload: Json(decode_json(record.payload)?),
+            params: record.params.map(decode_json).transpose()?.map(Json),
+            tags: record.tags,
+            metadata: record.metadata,
+            correlation_id: record.correlation_id,
+            parent_job_id: record.parent_job_id,
+            checkpoint: record.checkpoint,
+            attempts: record.attempts,
+            max_attempts: record.max_attempts,
+            retry_backoff_secs: record.retry_backoff_secs,
+            timeout_secs: record.timeout_secs,
+        })
+    }
+}
+
+/// Columns are looked up by name, as `#[derive(sqlx::FromRow)]` does.
+impl FromRecord<&PgRow> for JobRow {
+    fn from_record(record: &PgRow) -> Result<Self, sqlx::Error> {
+        JobRow::from_row(record)
+    }
+}
+
+impl<R> FromRecord<R> for DomainJob
+where
+    JobRow: FromRecord<R>,
+{
+    fn from_record(record: R) -> Result<Self, sqlx::Error> {
+        DomainJob::try_from(JobRow::from_record(record)?)
+            .map_err(|err| sqlx::Error::Decode(err.into()))
+    }
+}
+
+/// Converts every record, stopping at the first one that doesn't.
+pub fn from_records<R, T: FromRecord<R>>(
+    records: impl IntoIterator<Item = R>,
+) -> Result<Vec<T>, sqlx::Error> {
+    records.into_iter().map(T::from_record).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use sqlx::Error;
+
+    use super::*;
+    use crate::Payload;
+
+    fn record(payload: Value, params: Option<Value>) -> JobRecord {
+        JobRecord {
+            id: 1,
+            status: JobStatus::Queued,
+            payload,
+            params,
+            tags: vec![],
+            metadata: json!({}),
+            correlation_id: None,
+            parent_job_id: None,
+            checkpoint: None,
+            attempts: 0,
+            max_attempts: None,
+            retry_backoff_secs: None,
+            timeout_secs: None,
+        }
+    }
+
+    /// A row with the columns of `JobRow`, `overrides` replacing some of them.
+    async fn pg_row(overrides: &[(&str, &str)]) -> PgRow {
+        let columns = [
+            ("id", "1::BIGINT"),
+            ("status", "'Queued'::JOB_STATUS"),
+            (
+                "payload",
+                r#"'{"type": "SendEmail", "email": "user@example.com"}'::JSONB"#,
+            ),
+            ("params", r#"'{"type": "FollowUp", "data": true}'::JSONB"#),
+            ("tags", "ARRAY['campaign:black-friday']"),
+            ("metadata", r#"'{"enqueuer": "test"}'::JSONB"#),
+            ("correlation_id", "NULL::TEXT"),
+            ("parent_job_id", "NULL::BIGINT"),
+            ("checkpoint", "NULL::JSONB"),
+            ("attempts", "0"),
+            ("max_attempts", "NULL::INTEGER"),
+            ("retry_backoff_secs", "NULL::FLOAT8"),
+            ("timeout_secs", "NULL::FLOAT8"),
+        ];
+        let columns: Vec<String> = columns
+            .iter()
+            .map(|(name, value)| {
+                let value = overrides
+                    .iter()
+                    .find(|(overridden, _)| overridden == name)
+                    .map_or(*value, |(_, value)| value);
+                format!("{} AS {}", value, name)
+            })
+            .collect();
+        let pg_pool = crate::must_get_pool().await;
+        sqlx::query(&format!("SELECT {}", columns.join(", ")))
+            .fetch_one(&pg_pool)
+            .await
+            .unwrap()
+    }
+
+    #[test]
+    fn record_payload_and_params_round_trip() {
+        let payload = json!({ "type": "SendEmail", "email": "user@example.com" });
+        let params = json!({ "type": "FollowUp", "data": true });
+        let job = JobRow::from_record(record(payload.clone(), Some(params.clone()))).unwrap();
+        assert_eq!(json!(job.payload), payload);
+        assert_eq!(json!(job.params), params);
+
+        let job = JobRow::from_record(record(json!({ "type": "NOOP" }), None)).unwrap();
+        assert!(matches!(job.payload.0, Payload::NOOP));
+        assert!(job.params.is_none());
+    }
+
+    #[test]
+    fn record_with_an_unknown_payload_type_fails() {
+        let unknown = record(json!({ "type": "SendFax", "number": "555" }), None);
+        assert!(matches!(
+            JobRow::from_record(unknown),
+            Err(Error::Decode(_))
+        ));
+
+        // Params carry their content next to the tag, not inside it.
+        let untagged = record(json!({ "type": "NOOP" }), Some(json!({ "FollowUp": true })));
+        assert!(matches!(
+            JobRow::from_record(untagged),
+            Err(Error::Decode(_))
+        ));
+    }
+
+    #[test]
+    fn record_with_null_metadata_converts() {
+        let mut record = record(json!({ "type": "NOOP" }), None);
+        record.metadata = Value::Null;
+        let job = DomainJob::from_record(record).unwrap();
+        assert_eq!(job.metadata, Value::Null);
+    }
+
+    #[test]
+    fn domain_job_with_an_id_out_of_range_fails() {
+        let mut record = record(json!({ "type": "NOOP" }), None);
+        record.id = i64::from(u32::MAX) + 1;
+        assert!(matches!(
+            DomainJob::from_record(record),
+            Err(Error::Decode(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn pg_row_payload_and_params_round_trip() {
+        let job = JobRow::from_record(&pg_row(&[]).await).unwrap();
+        assert_eq!(
+            json!(job.payload),
+            json!({ "type": "SendEmail", "email": "user@example.com" })
+        );
+        assert_eq!(
+            json!(job.params),
+            json!({ "type": "FollowUp", "data": true })
+        );
+        assert_eq!(job.tags, vec!["campaign:black-friday"]);
+    }
+
+    #[tokio::test]
+    async fn pg_row_with_null_metadata_or_tags_fails() {
+        for column in ["metadata", "tags"] {
+            let row = pg_row(&[(column, "NULL")]).await;
+            let err = JobRow::from_record(&row).unwrap_err();
+            assert!(
+                matches!(&err, Error::ColumnDecode { index, .. } if index.contains(column)),
+                "{}: {}",
+                column,
+                err
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn pg_row_with_an_unknown_status_fails() {
+        let row = pg_row(&[("status", "'Cancelled'")]).await;
+        let err = JobRow::from_record(&row).unwrap_err();
+        assert!(
+            matches!(&err, Error::ColumnDecode { index, .. } if index.contains("status")),
+            "{}",
+            err
+        );
+    }
+}
@@ -0,0 +1,73 @@
+use sqlx::PgPool;
+
+/// How long jobs that failed for good are kept, unless told otherwise.
+pub const DEFAULT_RETENTION_DAYS: i32 = 30;
+
+/// How often `scheduler` expires jobs, the retention being counted in days.
+pub const EXPIRY_INTERVAL_SECS: u64 = 3600;
+
+/// Expired jobs are deleted by batches, each in its own transaction, so that
+/// a large backlog doesn't hold locks for long.
+const BATCH_SIZE: i64 = 1000;
+
+/// Deletes the jobs that failed for good more than `retention_days` ago, or
+/// moves them to `archived_jobs` along with their effects and events when
+/// `archive` is set. Returns how many were expired.
+///
+/// Kept jobs are left alone. The children of an expired job stay, their
+/// `parent_job_id` cleared by the foreign key: `tree` then starts from them.
+pub async fn expire_dead_jobs(
+    pg_pool: &PgPool,
+    retention_days: i32,
+    archive: bool,
+) -> Result<u64, sqlx::Error> {
+    let mut expired = 0;
+    loop {
+        let result = sqlx::query!(
+            r#"
+            WITH expired AS (
+                SELECT id
+                FROM jobs
+                WHERE status = 'Failed'
+                  AND NOT keep
+                  AND finished_at < now() - make_interval(days => $1)
+                ORDER BY id
+                LIMIT $2
+                FOR UPDATE SKIP LOCKED
+            ), archived AS (
+                INSERT INTO archived_jobs (job_id, job)
+                SELECT id, to_jsonb(jobs) || jsonb_build_object('effects', COALESCE(
+                    (SELECT jsonb_agg(to_jsonb(effects) - 'job_id' ORDER BY created_at, key) FROM effects WHERE job_id = jobs.id),
+                    '[]'
+                ), 'events', COALESCE(
+                    (SELECT jsonb_agg(to_jsonb(job_events) - 'job_id' ORDER BY id) FROM job_events WHERE job_id = jobs.id),
+                    '[]'
+                ))
+                FROM jobs
+                WHERE $3 AND id IN (SELECT id FROM expired)
+            )
+            DELETE FROM jobs
+            WHERE id IN (SELECT id FROM expired)
+            "#,
+            retention_days,
+            BATCH_SIZE,
+            archive,
+        )
+        .execute(pg_pool)
+        .await?;
+
+        expired += result.rows_affected();
+        if result.rows_affected() < BATCH_SIZE as u64 {
+            return Ok(expired);
+        }
+    }
+}
+
+/// Exempts a job from expiry, or makes it expire again. Returns `false` when
+/// there is no such job.
+pub async fn keep(pg_pool: &PgPool, job_id: i64, keep: bool) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query!("UPDATE jobs SET keep = $1 WHERE id = $2", keep, job_id)
+        .execute(pg_pool)
+        .await?;
+    Ok(result.rows_affected() > 0)
+}
@@ -0,0 +1,48 @@
+use std::future::Future;
+use std::time::Duration;
+
+const MAX_ATTEMPTS: u32 = 5;
+const INITIAL_BACKOFF: Duration = Duration::from_millis(100);
+
+/// Errors worth trying again: the same query is expected to go through once
+/// the conflict is gone or the connection is back.
+pub fn is_transient(err: &sqlx::Error) -> bool {
+    match err {
+        sqlx::Error::Io(_) | sqlx::Error::PoolTimedOut => true,
+        sqlx::Error::Database(err) => match err.code() {
+            // serialization_failure, deadlock_detected
+            Some(code) if code == "40001" || code == "40P01" => true,
+            // connection_exception class
+            Some(code) if code.starts_with("08") => true,
+            // admin_shutdown, crash_shutdown, cannot_connect_now
+            Some(code) if code == "57P01" || code == "57P02" || code == "57P03" => true,
+            _ => false,
+        },
+        _ => false,
+    }
+}
+
+/// Runs `operation` until it succeeds, fails for good, or ran out of attempts,
+/// doubling the delay between attempts.
+pub async fn with_retry<T, F, Fut>(name: &str, mut operation: F) -> Result<T, sqlx::Error>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, sqlx::Error>>,
+{
+    let mut backoff = INITIAL_BACKOFF;
+    let mut attempt = 1;
+    loop {
+        match operation().await {
+            Err(err) if attempt < MAX_ATTEMPTS && is_transient(&err) => {
+                println!(
+                    "{} failed ({}), retrying in {:?} ({}/{})",
+                    name, err, backoff, attempt, MAX_ATTEMPTS
+                );
+                tokio::time::sleep(backoff).await;
+                backoff *= 2;
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
@@ -0,0 +1,185 @@
+use std::fmt;
+
+use sqlx::migrate::Migrator;
+use sqlx::PgPool;
+
+use crate::error::ErrorKind;
+use crate::JobStatus;
+
+pub static MIGRATOR: Migrator = sqlx::migrate!();
+
+/// The columns of `jobs` the binary reads or writes, as `format_type` names
+/// their types, and whether they are nullable. A migration changing `jobs`
+/// must update it, or the binary refuses to start against its own schema.
+const JOBS_COLUMNS: &[(&str, &str, bool)] = &[
+    ("id", "bigint", false),
+    ("status", "job_status", false),
+    ("payload", "jsonb", false),
+    ("params", "jsonb", true),
+    ("tags", "text[]", false),
+    ("metadata", "jsonb", false),
+    ("correlation_id", "text", true),
+    ("parent_job_id", "bigint", true),
+    ("run_at", "timestamp with time zone", false),
+    ("workflow_id", "bigint", true),
+    ("workflow_step", "integer", true),
+    ("workflow_compensation", "boolean", false),
+    ("checkpoint", "jsonb", true),
+    ("attempts", "integer", false),
+    ("locked_until", "timestamp with time zone", true),
+    ("finished_at", "timestamp with time zone", true),
+    ("queue", "text", false),
+    ("started_at", "timestamp with time zone", true),
+    ("job_type", "text", true),
+    ("last_error", "text", true),
+    ("error_kind", "error_kind", true),
+    ("created_at", "timestamp with time zone", false),
+    ("concurrency_key", "text", true),
+    ("tenant", "text", false),
+    ("priority", "integer", false),
+    ("keep", "boolean", false),
+    ("max_attempts", "integer", true),
+    ("retry_backoff_secs", "double precision", true),
+    ("timeout_secs", "double precision", true),
+];
+
+/// Something in the database that isn't what the binary expects, `None`
+/// standing for a missing object on either side.
+pub struct Drift {
+    pub object: String,
+    pub expected: Option<String>,
+    pub found: Option<String>,
+}
+
+/// Printed as a diff, from what the binary expects to what the database has.
+impl fmt::Display for Drift {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let describe = |side: &Option<String>| side.clone().unwrap_or_else(|| "(none)".to_string());
+        writeln!(f, "-{:<24} {}", self.object, describe(&self.expected))?;
+        write!(f, "+{:<24} {}", self.object, describe(&self.found))
+    }
+}
+
+/// sqlx encodes unit variants by their name.
+fn variants<T: fmt::Debug>(all: &[T]) -> Vec<String> {
+    all.iter().map(|variant| format!("{:?}", variant)).collect()
+}
+
+fn column(sql_type: &str, nullable: bool) -> String {
+    format!("{}{}", sql_type, if nullable { "" } else { " NOT NULL" })
+}
+
+/// Compares the `jobs` table and the enums the binary decodes with the live
+/// database. Columns the binary doesn't know about are fine, e.g. added by a
+/// migration ahead of a deploy, unless they would make its inserts fail.
+pub async fn drift(pg_pool: &PgPool) -> Result<Vec<Drift>, sqlx::Error> {
+    let live: Vec<(String, String, bool, bool)> = sqlx::query_as(
+        r#"
+        SELECT attname::TEXT, format_type(atttypid, atttypmod), attnotnull,
+               atthasdef OR attidentity <> '' OR attgenerated <> ''
+        FROM pg_attribute
+        WHERE attrelid = to_regclass('jobs')
+          AND attnum > 0
+          AND NOT attisdropped
+        ORDER BY attnum
+        "#,
+    )
+    .fetch_all(pg_pool)
+    .await?;
+
+    let mut drift = vec![];
+    if live.is_empty() {
+        drift.push(Drift {
+            object: "jobs".to_string(),
+            expected: Some("table".to_string()),
+            found: None,
+        });
+    } else {
+        for (name, sql_type, nullable) in JOBS_COLUMNS {
+            let expected = column(sql_type, *nullable);
+            let found = live
+                .iter()
+                .find(|(live_name, ..)| live_name == name)
+                .map(|(_, sql_type, not_null, _)| column(sql_type, !not_null));
+            if found.as_ref() != Some(&expected) {
+                drift.push(Drift {
+                    object: format!("jobs.{}", name),
+                    expected: Some(expected),
+                    found,
+                });
+            }
+        }
+        for (name, sql_type, not_null, has_default) in &live {
+            let known = JOBS_COLUMNS.iter().any(|(known, ..)| known == name);
+            if !known && *not_null && !has_default {
+                drift.push(Drift {
+                    object: format!("jobs.{}", name),
+                    expected: None,
+                    found: Some(format!("{} NOT NULL, without a default", sql_type)),
+                });
+            }
+        }
+    }
+
+    let enums: [(&str, Vec<String>); 2] = [
+        ("JOB_STATUS", variants(&JobStatus::ALL)),
+        ("ERROR_KIND", variants(&ErrorKind::ALL)),
+    ];
+    for (name, expected) in enums {
+        let found: Vec<String> = sqlx::query_scalar(
+            "SELECT enumlabel::TEXT FROM pg_enum WHERE enumtypid = to_regtype($1) ORDER BY enumsortorder",
+        )
+        .bind(name)
+        .fetch_all(pg_pool)
+        .await?;
+
+        // Variants are matched by name, their order doesn't matter.
+        let missing = expected.iter().any(|variant| !found.contains(variant));
+        let unknown = found.iter().any(|variant| !expected.contains(variant));
+        if missing || unknown {
+            drift.push(Drift {
+                object: name.to_string(),
+                expected: Some(expected.join(", ")),
+                found: Some(found.join(", ")).filter(|_| !found.is_empty()),
+            });
+        }
+    }
+
+    Ok(drift)
+}
+
+/// Prints `drift` as a diff, followed by what to do about it.
+pub fn report(drift: &[Drift]) {
+    eprintln!();
+    eprintln!("--- expected by this binary");
+    eprintln!("+++ found in the database");
+    for drift in drift {
+        eprintln!("{}", drift);
+    }
+    eprintln!();
+    eprintln!("Apply the pending migrations, or run the binary built for this database.");
+}
+
+/// Versions of the migrations shipped with this binary that were not applied
+/// to the database yet.
+pub async fn pending_migrations(pg_pool: &PgPool) -> Result<Vec<i64>, sqlx::Error> {
+    // Not checked at compile time: `_sqlx_migrations` only exists once
+    // `sqlx migrate run` went through.
+    let applied: Vec<i64> =
+        sqlx::query_scalar("SELECT version FROM _sqlx_migrations WHERE success")
+            .fetch_all(pg_pool)
+            .await?;
+
+    Ok(MIGRATOR
+        .iter()
+        .map(|migration| migration.version)
+        .filter(|version| !applied.contains(version))
+        .collect())
+}
+
+/// The latest migration applied to the database, `None` on an empty one.
+pub async fn version(pg_pool: &PgPool) -> Result<Option<i64>, sqlx::Error> {
+    sqlx::query_scalar("SELECT max(version) FROM _sqlx_migrations WHERE success")
+        .fetch_one(pg_pool)
+        .await
+}
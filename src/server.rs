@@ -0,0 +1,193 @@
+use std::net::IpAddr;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use serde_json::json;
+use sqlx::PgPool;
+use tokio::io::AsyncBufRead;
+use tokio::io::AsyncBufReadExt;
+use tokio::io::AsyncReadExt;
+use tokio::io::AsyncWriteExt;
+use tokio::io::BufReader;
+use tokio::net::TcpListener;
+use tokio::net::TcpStream;
+
+use crate::api;
+use crate::schema;
+use crate::stats;
+
+/// Requests beyond these limits are turned down before being read further:
+/// the server is reachable by anyone who can reach the database's jobs.
+const MAX_BODY_LEN: usize = 1024 * 1024;
+const MAX_LINE_LEN: usize = 8 * 1024;
+const MAX_HEADERS: usize = 100;
+
+/// A bare-bones HTTP server: it only ever needs to answer a few GET requests
+/// from monitoring tools, and the small JSON API of `api`, which doesn't
+/// warrant a web framework.
+///
+/// The API hands out and rewrites jobs: with a `token`, requests changing
+/// jobs must carry it as `Authorization: Bearer <token>`; without one, they
+/// are only answered from the machine itself, whatever `address` is bound.
+pub async fn serve(
+    pg_pool: PgPool,
+    address: IpAddr,
+    port: u16,
+    token: Option<String>,
+) -> std::io::Result<()> {
+    let listener = TcpListener::bind((address, port)).await?;
+    println!("Listening on http://{}", SocketAddr::new(address, port));
+    let token: Option<Arc<str>> = token.map(Arc::from);
+
+    loop {
+        let (stream, peer) = listener.accept().await?;
+        let pg_pool = pg_pool.clone();
+        let token = token.clone();
+        tokio::spawn(async move {
+            if let Err(err) = respond(&pg_pool, stream, peer, token.as_deref()).await {
+                eprintln!("Could not answer request: {}", err);
+            }
+        });
+    }
+}
+
+/// Compares in constant time, not to tell how much of a guessed token was right.
+fn same_token(given: &str, token: &str) -> bool {
+    given.len() == token.len()
+        && given
+            .bytes()
+            .zip(token.bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+async fn respond(
+    pg_pool: &PgPool,
+    mut stream: TcpStream,
+    peer: SocketAddr,
+    token: Option<&str>,
+) -> std::io::Result<()> {
+    let mut reader = BufReader::new(&mut stream);
+
+    let mut request_line = String::new();
+    if read_line(&mut reader, &mut request_line).await?.is_none() {
+        return send(stream, "414 URI Too Long", "text/plain", "URI too long\n").await;
+    }
+    // Only the body length and the credentials matter among the headers, but
+    // they must all be read before the body.
+    let mut content_length = 0;
+    let mut bearer = None;
+    let mut header = String::new();
+    for count in 0.. {
+        header.clear();
+        let read = match read_line(&mut reader, &mut header).await? {
+            Some(read) if count < MAX_HEADERS || read <= 2 => read,
+            _ => {
+                let status = "431 Request Header Fields Too Large";
+                return send(stream, status, "text/plain", "Headers too large\n").await;
+            }
+        };
+        if read <= 2 {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("content-length") {
+                content_length = value.trim().parse().unwrap_or(0);
+            } else if name.eq_ignore_ascii_case("authorization") {
+                bearer = value.trim().strip_prefix("Bearer ").map(str::to_string);
+            }
+        }
+    }
+    if content_length > MAX_BODY_LEN {
+        let status = "413 Payload Too Large";
+        return send(stream, status, "text/plain", "Body too large\n").await;
+    }
+    let mut body = vec![0; content_length];
+    reader.read_exact(&mut body).await?;
+
+    let mut request = request_line.split_whitespace();
+    let method = request.next().unwrap_or("GET");
+    let path = request.next().unwrap_or("/");
+
+    let authorized = match token {
+        Some(token) => bearer.is_some_and(|bearer| same_token(&bearer, token)),
+        None => peer.ip().is_loopback(),
+    };
+    if let Some((status, body)) = api::route(pg_pool, method, path, &body, authorized).await {
+        return send(
+            stream,
+            status,
+            "application/json",
+            &format!("{}\n", json!(body)),
+        )
+        .await;
+    }
+
+    let (status, body) = match path {
+        "/healthz" => ("200 OK", "ok\n".to_string()),
+        "/readyz" => match readiness_failures(pg_pool).await {
+            failures if failures.is_empty() => ("200 OK", "ready\n".to_string()),
+            failures => ("503 Service Unavailable", failures.join("\n") + "\n"),
+        },
+        "/metrics" => match stats::fetch(pg_pool).await {
+            Ok(stats) => ("200 OK", stats.to_prometheus()),
+            Err(err) => ("500 Internal Server Error", format!("{}\n", err)),
+        },
+        _ => ("404 Not Found", "Not found\n".to_string()),
+    };
+
+    send(stream, status, "text/plain", &body).await
+}
+
+/// Reads a line, or returns `None` once past `MAX_LINE_LEN` bytes.
+async fn read_line<R: AsyncBufRead + Unpin>(
+    reader: &mut R,
+    line: &mut String,
+) -> std::io::Result<Option<usize>> {
+    let read = reader.take(MAX_LINE_LEN as u64 + 1).read_line(line).await?;
+    Ok((read <= MAX_LINE_LEN).then_some(read))
+}
+
+async fn send(
+    mut stream: TcpStream,
+    status: &str,
+    content_type: &str,
+    body: &str,
+) -> std::io::Result<()> {
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        content_type,
+        body.len(),
+        body
+    );
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await
+}
+
+/// Lists what prevents this process from doing useful work, if anything.
+///
+/// There is no listener to check: answering at all means the HTTP listener
+/// is up, and this process holds no `LISTEN` connection. Workers poll for
+/// jobs, and only `sqlx-pb alerts`, a process of its own, listens for
+/// notifications.
+async fn readiness_failures(pg_pool: &PgPool) -> Vec<String> {
+    if let Err(err) = sqlx::query("SELECT 1").execute(pg_pool).await {
+        return vec![format!("database unreachable: {}", err)];
+    }
+
+    let mut failures = match schema::pending_migrations(pg_pool).await {
+        Ok(pending) if pending.is_empty() => vec![],
+        Ok(pending) => vec![format!("migrations not applied: {:?}", pending)],
+        Err(err) => vec![format!("could not check migrations: {}", err)],
+    };
+    match schema::drift(pg_pool).await {
+        Ok(drift) => failures.extend(
+            drift
+                .iter()
+                .map(|drift| format!("schema drift on {}", drift.object)),
+        ),
+        Err(err) => failures.push(format!("could not check the schema: {}", err)),
+    }
+    failures
+}
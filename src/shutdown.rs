@@ -0,0 +1,65 @@
+use tokio::signal::unix::signal;
+use tokio::signal::unix::SignalKind;
+use tokio::sync::watch;
+
+/// How the worker was asked to stop.
+#[derive(Clone, Copy, PartialEq, PartialOrd, Debug)]
+pub enum Shutdown {
+    Not,
+    /// SIGTERM (or SIGINT): finish the job at hand, hand the other claimed
+    /// jobs back to the queue.
+    Soft,
+    /// SIGQUIT, or a second SIGTERM: hand every claimed job back right away,
+    /// the one at hand included, so that other workers reclaim them.
+    Hard,
+}
+
+pub fn listen_for_signals() -> watch::Receiver<Shutdown> {
+    let (sender, receiver) = watch::channel(Shutdown::Not);
+
+    let mut terminate = signal(SignalKind::terminate()).expect("cannot listen to SIGTERM");
+    let mut interrupt = signal(SignalKind::interrupt()).expect("cannot listen to SIGINT");
+    let mut quit = signal(SignalKind::quit()).expect("cannot listen to SIGQUIT");
+
+    tokio::spawn(async move {
+        let mut shutdown = Shutdown::Not;
+        while shutdown != Shutdown::Hard {
+            let received = tokio::select! {
+                _ = terminate.recv() => "SIGTERM",
+                _ = interrupt.recv() => "SIGINT",
+                _ = quit.recv() => "SIGQUIT",
+            };
+
+            shutdown = if received != "SIGQUIT" && shutdown == Shutdown::Not {
+                println!(
+                    "{} received: soft shutdown, finishing the job at hand (send it again, or SIGQUIT, to stop right away)",
+                    received
+                );
+                Shutdown::Soft
+            } else {
+                println!(
+                    "{} received: hard shutdown, releasing every claimed job",
+                    received
+                );
+                Shutdown::Hard
+            };
+
+            if sender.send(shutdown).is_err() {
+                return;
+            }
+        }
+    });
+
+    receiver
+}
+
+/// Resolves once the shutdown reached `level`, a hard shutdown implying a
+/// soft one.
+pub async fn requested(mut shutdown: watch::Receiver<Shutdown>, level: Shutdown) {
+    while *shutdown.borrow() < level {
+        if shutdown.changed().await.is_err() {
+            // Nobody is left to ask for a shutdown.
+            std::future::pending::<()>().await;
+        }
+    }
+}
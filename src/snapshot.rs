@@ -0,0 +1,434 @@
+use std::collections::HashMap;
+use std::io::BufRead;
+use std::io::Write;
+
+use serde::Deserialize;
+use serde::Serialize;
+use sqlx::PgConnection;
+use sqlx::PgPool;
+
+use crate::schema;
+
+/// Bumped whenever the layout of the archive changes.
+const FORMAT: u32 = 2;
+
+/// How many rows are read, and restored, at once.
+const PAGE_ROWS: usize = 200;
+
+/// Every table holding queue state, in an order that satisfies their foreign
+/// keys. Queues come last: jobs can't be inserted into a draining one.
+/// Payload formats come after the jobs, which are restored as they were
+/// stored rather than as a producer would write them.
+const TABLES: &[&str] = &[
+    "blobs",
+    "workflows",
+    "jobs",
+    "archived_jobs",
+    "effects",
+    "job_events",
+    "usage",
+    "circuit_breakers",
+    "backfills",
+    "queue_settings",
+    "maintenance_windows",
+    "payload_formats",
+    "queues",
+];
+
+/// The first line of an archive. Each table follows as a `TableStart` line,
+/// then one line per row.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct Header {
+    pub format: u32,
+    /// The latest migration applied to the source database: rows are only
+    /// restored into the same schema.
+    pub schema_version: i64,
+    pub taken_at: String,
+}
+
+/// Rows are arrays of values, kept in their Postgres text form, as `COPY`
+/// does: it is exact for every type, and tells a SQL `NULL` from a JSON
+/// `null`. Generated columns are left out, they get computed again on
+/// restore.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct TableStart {
+    table: String,
+    columns: Vec<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Line {
+    Table(TableStart),
+    Row(Vec<Option<String>>),
+}
+
+/// A column that can be written to: its quoted name, its type, and whether
+/// it is part of the primary key.
+struct Column {
+    name: String,
+    quoted: String,
+    sql_type: String,
+    key: bool,
+}
+
+async fn columns(conn: &mut PgConnection, table: &str) -> Result<Vec<Column>, sqlx::Error> {
+    let columns: Vec<(String, String, String, bool)> = sqlx::query_as(
+        r#"
+        SELECT attname::TEXT, quote_ident(attname), format_type(atttypid, atttypmod),
+               EXISTS (
+                   SELECT 1
+                   FROM pg_index
+                   WHERE indrelid = attrelid AND indisprimary AND attnum = ANY(indkey)
+               )
+        FROM pg_attribute
+        WHERE attrelid = $1::TEXT::REGCLASS
+          AND attnum > 0
+          AND NOT attisdropped
+          AND attgenerated = ''
+        ORDER BY attnum
+        "#,
+    )
+    .bind(table)
+    .fetch_all(&mut *conn)
+    .await?;
+
+    Ok(columns
+        .into_iter()
+        .map(|(name, quoted, sql_type, key)| Column {
+            name,
+            quoted,
+            sql_type,
+            key,
+        })
+        .collect())
+}
+
+fn write_line(out: &mut impl Write, value: &impl Serialize) -> std::io::Result<()> {
+    serde_json::to_writer(&mut *out, value)?;
+    out.write_all(b"\n")
+}
+
+/// Writes every table to `out` within one repeatable-read transaction, so
+/// that the snapshot is consistent even while workers are running. Returns
+/// the archive's header and the number of rows taken per table.
+///
+/// Tables are read a page at a time, in primary key order, and rows written
+/// as they come: neither side holds more than a page, however big the
+/// tables.
+pub async fn take(
+    pg_pool: &PgPool,
+    out: &mut impl Write,
+) -> Result<(Header, Vec<(&'static str, usize)>), sqlx::Error> {
+    let schema_version = schema::version(pg_pool).await?.unwrap_or_default();
+
+    let mut tx = pg_pool.begin().await?;
+    sqlx::query("SET TRANSACTION ISOLATION LEVEL REPEATABLE READ, READ ONLY")
+        .execute(&mut tx)
+        .await?;
+
+    let taken_at: String = sqlx::query_scalar("SELECT now()::TEXT")
+        .fetch_one(&mut tx)
+        .await?;
+    let header = Header {
+        format: FORMAT,
+        schema_version,
+        taken_at,
+    };
+    write_line(out, &header)?;
+
+    let mut taken = vec![];
+    for table in TABLES {
+        let columns = columns(&mut tx, table).await?;
+        let values: Vec<String> = columns
+            .iter()
+            .map(|column| format!("t.{}::TEXT", column.quoted))
+            .collect();
+        let keys: Vec<(usize, &Column)> = columns
+            .iter()
+            .enumerate()
+            .filter(|(_, column)| column.key)
+            .collect();
+        let key_names: Vec<String> = keys
+            .iter()
+            .map(|(_, column)| format!("t.{}", column.quoted))
+            .collect();
+        let key_values: Vec<String> = keys
+            .iter()
+            .enumerate()
+            .map(|(index, (_, column))| format!("($1::TEXT[])[{}]::{}", index + 1, column.sql_type))
+            .collect();
+
+        // Table names come from `TABLES`, column names are quoted. The page
+        // starts after the key of the last row of the previous one.
+        let sql = format!(
+            "SELECT ARRAY[{}] FROM {} t WHERE $1::TEXT[] IS NULL OR ({}) > ({}) ORDER BY {} LIMIT $2",
+            values.join(", "),
+            table,
+            key_names.join(", "),
+            key_values.join(", "),
+            key_names.join(", ")
+        );
+
+        write_line(
+            out,
+            &TableStart {
+                table: table.to_string(),
+                columns: columns.iter().map(|column| column.name.clone()).collect(),
+            },
+        )?;
+        let mut after: Option<Vec<String>> = None;
+        let mut rows = 0;
+        loop {
+            let page: Vec<Vec<Option<String>>> = sqlx::query_scalar(&sql)
+                .bind(&after)
+                .bind(PAGE_ROWS as i64)
+                .fetch_all(&mut tx)
+                .await?;
+            for row in &page {
+                write_line(out, row)?;
+            }
+            rows += page.len();
+            match page.last() {
+                Some(last) if page.len() == PAGE_ROWS => {
+                    after = Some(
+                        keys.iter()
+                            .map(|(index, _)| {
+                                last[*index].clone().expect("primary keys aren't null")
+                            })
+                            .collect(),
+                    );
+                }
+                _ => break,
+            }
+        }
+        taken.push((*table, rows));
+    }
+    tx.commit().await?;
+
+    Ok((header, taken))
+}
+
+/// Rows of a table waiting to be inserted, a page at a time.
+struct Pending<'t> {
+    table: &'static str,
+    columns: Vec<&'t Column>,
+    rows: Vec<Vec<Option<String>>>,
+}
+
+impl Pending<'_> {
+    /// Each value goes through the input function of its column's type, the
+    /// way `COPY` reads it. Values are sent one array per column, which no
+    /// single JSON value would have to hold.
+    async fn flush(&mut self, conn: &mut PgConnection) -> Result<(), String> {
+        if self.rows.is_empty() {
+            return Ok(());
+        }
+        let names: Vec<&str> = self
+            .columns
+            .iter()
+            .map(|column| column.quoted.as_str())
+            .collect();
+        let values: Vec<String> = self
+            .columns
+            .iter()
+            .enumerate()
+            .map(|(index, column)| format!("c{}::{}", index, column.sql_type))
+            .collect();
+        let arrays: Vec<String> = (1..=self.columns.len())
+            .map(|index| format!("${}::TEXT[]", index))
+            .collect();
+        let aliases: Vec<String> = (0..self.columns.len())
+            .map(|index| format!("c{}", index))
+            .collect();
+        let sql = format!(
+            "INSERT INTO {} ({}) OVERRIDING SYSTEM VALUE SELECT {} FROM UNNEST({}) AS row({})",
+            self.table,
+            names.join(", "),
+            values.join(", "),
+            arrays.join(", "),
+            aliases.join(", ")
+        );
+
+        let mut query = sqlx::query(&sql);
+        for index in 0..self.columns.len() {
+            let column: Vec<Option<String>> =
+                self.rows.iter_mut().map(|row| row[index].take()).collect();
+            query = query.bind(column);
+        }
+        query
+            .execute(&mut *conn)
+            .await
+            .map_err(|err| format!("failed to restore {}: {}", self.table, err))?;
+        self.rows.clear();
+        Ok(())
+    }
+}
+
+/// Loads the archive read from `input` into a database without any job,
+/// within one transaction: either every row is restored, or none is. Rows are
+/// inserted a page at a time, as they are read. Returns the archive's header
+/// and the number of rows restored per table.
+pub async fn restore(
+    pg_pool: &PgPool,
+    input: impl BufRead,
+) -> Result<(Header, Vec<(&'static str, usize)>), String> {
+    let mut lines = input.lines().enumerate().map(|(index, line)| {
+        line.map(|line| (index + 1, line))
+            .map_err(|err| format!("failed to read the archive: {}", err))
+    });
+
+    let (_, first) = lines.next().ok_or("empty archive")??;
+    let header: Header = serde_json::from_str(&first).map_err(|err| format!("line 1: {}", err))?;
+    if header.format != FORMAT {
+        return Err(format!(
+            "unsupported archive format {} (expected {})",
+            header.format, FORMAT
+        ));
+    }
+    let schema_version = schema::version(pg_pool)
+        .await
+        .map_err(|err| format!("apply the migrations first ({})", err))?
+        .unwrap_or_default();
+    if schema_version != header.schema_version {
+        return Err(format!(
+            "the archive was taken at migration {}, the database is at {}",
+            header.schema_version, schema_version
+        ));
+    }
+
+    let mut tx = pg_pool.begin().await.map_err(|err| err.to_string())?;
+    let mut restored = vec![];
+    let mut columns_by_table = vec![];
+    for table in TABLES {
+        let empty: bool =
+            sqlx::query_scalar(&format!("SELECT NOT EXISTS (SELECT 1 FROM {})", table))
+                .fetch_one(&mut tx)
+                .await
+                .map_err(|err| err.to_string())?;
+        if !empty {
+            return Err(format!(
+                "table {} isn't empty, restore into a fresh database",
+                table
+            ));
+        }
+        let columns: HashMap<String, Column> = columns(&mut tx, table)
+            .await
+            .map_err(|err| err.to_string())?
+            .into_iter()
+            .map(|column| (column.name.clone(), column))
+            .collect();
+        columns_by_table.push(columns);
+        restored.push((*table, 0));
+    }
+
+    // The events of the restored jobs are in the archive: the trigger would
+    // record their insertion on top of them.
+    sqlx::query("ALTER TABLE jobs DISABLE TRIGGER jobs_record_event")
+        .execute(&mut tx)
+        .await
+        .map_err(|err| err.to_string())?;
+
+    // Tables come in the order of `TABLES`, for their foreign keys to hold.
+    let mut next_table = 0;
+    let mut pending: Option<(usize, Pending)> = None;
+    for line in lines {
+        let (number, line) = line?;
+        let line: Line =
+            serde_json::from_str(&line).map_err(|err| format!("line {}: {}", number, err))?;
+        match line {
+            Line::Table(start) => {
+                if let Some((_, pending)) = pending.as_mut() {
+                    pending.flush(&mut tx).await?;
+                }
+                let index = TABLES
+                    .iter()
+                    .position(|table| *table == start.table)
+                    .ok_or_else(|| format!("unknown table in archive: {}", start.table))?;
+                if index < next_table {
+                    return Err(format!(
+                        "line {}: table {} out of order",
+                        number, start.table
+                    ));
+                }
+                next_table = index + 1;
+
+                let columns = start
+                    .columns
+                    .iter()
+                    .map(|name| {
+                        columns_by_table[index].get(name).ok_or_else(|| {
+                            format!("no column {}.{} to restore into", start.table, name)
+                        })
+                    })
+                    .collect::<Result<_, _>>()?;
+                pending = Some((
+                    index,
+                    Pending {
+                        table: TABLES[index],
+                        columns,
+                        rows: vec![],
+                    },
+                ));
+            }
+            Line::Row(row) => {
+                let (index, table) = pending
+                    .as_mut()
+                    .ok_or_else(|| format!("line {}: a row before any table", number))?;
+                if row.len() != table.columns.len() {
+                    return Err(format!(
+                        "line {}: {} values for the {} columns of {}",
+                        number,
+                        row.len(),
+                        table.columns.len(),
+                        table.table
+                    ));
+                }
+                table.rows.push(row);
+                restored[*index].1 += 1;
+                if table.rows.len() == PAGE_ROWS {
+                    table.flush(&mut tx).await?;
+                }
+            }
+        }
+    }
+    if let Some((_, pending)) = pending.as_mut() {
+        pending.flush(&mut tx).await?;
+    }
+    sqlx::query("ALTER TABLE jobs ENABLE TRIGGER jobs_record_event")
+        .execute(&mut tx)
+        .await
+        .map_err(|err| err.to_string())?;
+
+    // Identity columns were written explicitly, their sequences must catch up
+    // for new rows not to collide with the restored ones.
+    let identities: Vec<(String, String, String)> = sqlx::query_as(
+        r#"
+        SELECT table_name::TEXT, column_name::TEXT, quote_ident(column_name)
+        FROM information_schema.columns
+        WHERE table_schema = current_schema()
+          AND is_identity = 'YES'
+          AND table_name = ANY($1)
+        "#,
+    )
+    .bind(TABLES)
+    .fetch_all(&mut tx)
+    .await
+    .map_err(|err| err.to_string())?;
+    for (table, column, quoted) in identities {
+        sqlx::query(&format!(
+            "SELECT setval(pg_get_serial_sequence($1, $2), COALESCE(max({}), 0) + 1, false) FROM {}",
+            quoted, table
+        ))
+        .bind(&table)
+        .bind(&column)
+        .execute(&mut tx)
+        .await
+        .map_err(|err| err.to_string())?;
+    }
+
+    tx.commit().await.map_err(|err| err.to_string())?;
+    Ok((header, restored))
+}
@@ -0,0 +1,324 @@
+use std::fmt::Write;
+
+use serde::Serialize;
+use sqlx::postgres::PgArguments;
+use sqlx::postgres::PgRow;
+use sqlx::query::Map;
+use sqlx::PgPool;
+use sqlx::Postgres;
+
+/// The processing rate is measured over that many seconds.
+const RATE_WINDOW_SECS: f64 = 300.0;
+
+#[derive(Serialize, Debug)]
+pub struct Stats {
+    pub queued: i64,
+    pub running: i64,
+    pub failed: i64,
+    pub done: i64,
+    /// Queued jobs that are due, plus the running ones.
+    pub pending: i64,
+    /// Jobs finished per second, over the last `RATE_WINDOW_SECS`.
+    pub processing_rate: f64,
+    /// How long the current workers need to go through the pending jobs, this
+    /// is the signal to scale them on. `None` when pending jobs aren't being
+    /// processed at all.
+    pub estimated_drain_seconds: Option<f64>,
+    pub by_type: Vec<TypeStats>,
+}
+
+/// The same counts, for the jobs of one payload variant.
+#[derive(Serialize, Debug)]
+pub struct TypeStats {
+    pub job_type: String,
+    pub queued: i64,
+    pub running: i64,
+    pub failed: i64,
+    pub done: i64,
+    /// Share of the finished jobs that failed, `None` until one finishes.
+    pub failure_rate: Option<f64>,
+    /// How long the latest attempt of the finished jobs took, on average.
+    pub average_duration_seconds: Option<f64>,
+    /// Time left before the type's circuit breaker closes, `None` when it
+    /// isn't open.
+    pub paused_for_seconds: Option<f64>,
+}
+
+/// How many jobs there are in each status, over all payload types.
+pub struct Counts {
+    queued: i64,
+    due: i64,
+    running: i64,
+    failed: i64,
+    done: i64,
+    recently_finished: i64,
+}
+
+/// The queries behind `fetch`, which `explain` analyzes too.
+pub fn counts_query(
+) -> Map<'static, Postgres, impl FnMut(PgRow) -> Result<Counts, sqlx::Error> + Send, PgArguments> {
+    sqlx::query_as!(
+        Counts,
+        r#"
+        SELECT count(*) FILTER (WHERE status = 'Queued') AS "queued!",
+               count(*) FILTER (WHERE status = 'Queued' AND run_at <= now()) AS "due!",
+               count(*) FILTER (WHERE status = 'Running') AS "running!",
+               count(*) FILTER (WHERE status = 'Failed') AS "failed!",
+               count(*) FILTER (WHERE status = 'Done') AS "done!",
+               count(*) FILTER (WHERE finished_at > now() - make_interval(secs => $1)) AS "recently_finished!"
+        FROM jobs
+        "#,
+        RATE_WINDOW_SECS,
+    )
+}
+
+pub fn by_type_query(
+) -> Map<'static, Postgres, impl FnMut(PgRow) -> Result<TypeStats, sqlx::Error> + Send, PgArguments>
+{
+    sqlx::query_as!(
+        TypeStats,
+        r#"
+        SELECT by_type.job_type AS "job_type!",
+               by_type.queued AS "queued!",
+               by_type.running AS "running!",
+               by_type.failed AS "failed!",
+               by_type.done AS "done!",
+               by_type.failure_rate,
+               by_type.average_duration_seconds,
+               EXTRACT(EPOCH FROM circuit_breakers.open_until - now())::FLOAT8 AS paused_for_seconds
+        FROM (
+            SELECT COALESCE(job_type, 'unknown') AS job_type,
+                   count(*) FILTER (WHERE status = 'Queued') AS queued,
+                   count(*) FILTER (WHERE status = 'Running') AS running,
+                   count(*) FILTER (WHERE status = 'Failed') AS failed,
+                   count(*) FILTER (WHERE status = 'Done') AS done,
+                   (count(*) FILTER (WHERE status = 'Failed'))::FLOAT8
+                       / NULLIF(count(*) FILTER (WHERE status IN ('Failed', 'Done')), 0) AS failure_rate,
+                   avg(EXTRACT(EPOCH FROM finished_at - started_at)::FLOAT8) AS average_duration_seconds
+            FROM jobs
+            GROUP BY 1
+        ) by_type
+        LEFT JOIN circuit_breakers
+               ON circuit_breakers.job_type = by_type.job_type
+              AND circuit_breakers.open_until > now()
+        ORDER BY 1
+        "#,
+    )
+}
+
+pub async fn fetch(pg_pool: &PgPool) -> Result<Stats, sqlx::Error> {
+    let counts = counts_query().fetch_one(pg_pool).await?;
+    let by_type = by_type_query().fetch_all(pg_pool).await?;
+
+    let pending = counts.due + counts.running;
+    let processing_rate = counts.recently_finished as f64 / RATE_WINDOW_SECS;
+    let estimated_drain_seconds = if pending == 0 {
+        Some(0.0)
+    } else if processing_rate > 0.0 {
+        Some(pending as f64 / processing_rate)
+    } else {
+        None
+    };
+
+    Ok(Stats {
+        queued: counts.queued,
+        running: counts.running,
+        failed: counts.failed,
+        done: counts.done,
+        pending,
+        processing_rate,
+        estimated_drain_seconds,
+        by_type,
+    })
+}
+
+impl Stats {
+    /// Renders the stats in the Prometheus text exposition format.
+    pub fn to_prometheus(&self) -> String {
+        let drain = match self.estimated_drain_seconds {
+            Some(seconds) => seconds.to_string(),
+            None => "+Inf".to_string(),
+        };
+
+        let mut metrics = format!(
+            "# HELP sqlx_pb_jobs Number of jobs by status.\n\
+             # TYPE sqlx_pb_jobs gauge\n\
+             sqlx_pb_jobs{{status=\"queued\"}} {}\n\
+             sqlx_pb_jobs{{status=\"running\"}} {}\n\
+             sqlx_pb_jobs{{status=\"failed\"}} {}\n\
+             sqlx_pb_jobs{{status=\"done\"}} {}\n\
+             # HELP sqlx_pb_pending_jobs Due and running jobs.\n\
+             # TYPE sqlx_pb_pending_jobs gauge\n\
+             sqlx_pb_pending_jobs {}\n\
+             # HELP sqlx_pb_processing_rate Jobs finished per second over the last 5 minutes.\n\
+             # TYPE sqlx_pb_processing_rate gauge\n\
+             sqlx_pb_processing_rate {}\n\
+             # HELP sqlx_pb_estimated_drain_seconds Time needed to go through the pending jobs at the current rate.\n\
+             # TYPE sqlx_pb_estimated_drain_seconds gauge\n\
+             sqlx_pb_estimated_drain_seconds {}\n",
+            self.queued,
+            self.running,
+            self.failed,
+            self.done,
+            self.pending,
+            self.processing_rate,
+            drain
+        );
+
+        metrics.push_str(
+            "# HELP sqlx_pb_jobs_by_type Number of jobs by payload type and status.\n\
+             # TYPE sqlx_pb_jobs_by_type gauge\n",
+        );
+        for stats in &self.by_type {
+            let job_type = label_value(&stats.job_type);
+            for (status, count) in [
+                ("queued", stats.queued),
+                ("running", stats.running),
+                ("failed", stats.failed),
+                ("done", stats.done),
+            ] {
+                let _ = writeln!(
+                    metrics,
+                    "sqlx_pb_jobs_by_type{{job_type=\"{}\",status=\"{}\"}} {}",
+                    job_type, status, count
+                );
+            }
+        }
+
+        metrics.push_str(
+            "# HELP sqlx_pb_failure_rate Share of the finished jobs that failed, by payload type.\n\
+             # TYPE sqlx_pb_failure_rate gauge\n",
+        );
+        for stats in &self.by_type {
+            if let Some(failure_rate) = stats.failure_rate {
+                let _ = writeln!(
+                    metrics,
+                    "sqlx_pb_failure_rate{{job_type=\"{}\"}} {}",
+                    label_value(&stats.job_type),
+                    failure_rate
+                );
+            }
+        }
+
+        metrics.push_str(
+            "# HELP sqlx_pb_average_duration_seconds Average run time of the finished jobs, by payload type.\n\
+             # TYPE sqlx_pb_average_duration_seconds gauge\n",
+        );
+        for stats in &self.by_type {
+            if let Some(duration) = stats.average_duration_seconds {
+                let _ = writeln!(
+                    metrics,
+                    "sqlx_pb_average_duration_seconds{{job_type=\"{}\"}} {}",
+                    label_value(&stats.job_type),
+                    duration
+                );
+            }
+        }
+
+        metrics.push_str(
+            "# HELP sqlx_pb_circuit_open Whether jobs of the payload type are paused by their circuit breaker.\n\
+             # TYPE sqlx_pb_circuit_open gauge\n",
+        );
+        for stats in &self.by_type {
+            let _ = writeln!(
+                metrics,
+                "sqlx_pb_circuit_open{{job_type=\"{}\"}} {}",
+                label_value(&stats.job_type),
+                u8::from(stats.paused_for_seconds.is_some())
+            );
+        }
+
+        metrics
+    }
+}
+
+/// Payload types come from the database, they may hold anything.
+fn label_value(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// What `aggregate` can break the jobs down by.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Dimension {
+    /// The UTC day the job was enqueued.
+    Day,
+    Queue,
+    Status,
+    Type,
+}
+
+impl Dimension {
+    pub fn parse(name: &str) -> Option<Dimension> {
+        match name {
+            "day" => Some(Dimension::Day),
+            "queue" => Some(Dimension::Queue),
+            "status" => Some(Dimension::Status),
+            "type" => Some(Dimension::Type),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Dimension::Day => "day",
+            Dimension::Queue => "queue",
+            Dimension::Status => "status",
+            Dimension::Type => "type",
+        }
+    }
+}
+
+/// One group of jobs, the dimensions it wasn't grouped by being `None`.
+#[derive(Debug)]
+pub struct Aggregate {
+    pub day: Option<String>,
+    pub queue: Option<String>,
+    pub status: Option<String>,
+    pub job_type: Option<String>,
+    pub jobs: i64,
+    pub failed: i64,
+    pub average_duration_seconds: Option<f64>,
+}
+
+impl Aggregate {
+    pub fn dimension(&self, dimension: Dimension) -> Option<&str> {
+        match dimension {
+            Dimension::Day => self.day.as_deref(),
+            Dimension::Queue => self.queue.as_deref(),
+            Dimension::Status => self.status.as_deref(),
+            Dimension::Type => self.job_type.as_deref(),
+        }
+    }
+}
+
+/// Counts jobs grouped by the given dimensions, in a single query: the ones
+/// not asked for are grouped on a constant NULL, which is a no-op.
+pub async fn aggregate(
+    pg_pool: &PgPool,
+    group_by: &[Dimension],
+) -> Result<Vec<Aggregate>, sqlx::Error> {
+    sqlx::query_as!(
+        Aggregate,
+        r#"
+        SELECT CASE WHEN $1 THEN to_char(created_at AT TIME ZONE 'UTC', 'YYYY-MM-DD') END AS day,
+               CASE WHEN $2 THEN queue END AS queue,
+               CASE WHEN $3 THEN status::TEXT END AS status,
+               CASE WHEN $4 THEN COALESCE(job_type, 'unknown') END AS job_type,
+               count(*) AS "jobs!",
+               count(*) FILTER (WHERE status = 'Failed') AS "failed!",
+               avg(EXTRACT(EPOCH FROM finished_at - started_at)::FLOAT8) AS average_duration_seconds
+        FROM jobs
+        GROUP BY 1, 2, 3, 4
+        ORDER BY 1, 2, 3, 4
+        "#,
+        group_by.contains(&Dimension::Day),
+        group_by.contains(&Dimension::Queue),
+        group_by.contains(&Dimension::Status),
+        group_by.contains(&Dimension::Type),
+    )
+    .fetch_all(pg_pool)
+    .await
+}
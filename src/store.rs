@@ -0,0 +1,1197 @@
+use std::collections::BTreeMap;
+
+use serde::Deserialize;
+use serde_json::json;
+use serde_json::Value;
+use sqlx::postgres::PgArguments;
+use sqlx::postgres::PgRow;
+use sqlx::query::Map;
+use sqlx::types::Json;
+use sqlx::Executor;
+use sqlx::PgPool;
+use sqlx::Postgres;
+use sqlx::Transaction;
+use tokio::sync::Mutex;
+use tokio::sync::MutexGuard;
+
+use crate::breaker;
+use crate::error::ErrorKind;
+use crate::error::JobError;
+use crate::plugin;
+use crate::query_log::logged;
+use crate::query_log::Logged;
+use crate::retry::with_retry;
+use crate::usage;
+use crate::worker::MAX_ATTEMPTS;
+use crate::workflow;
+use crate::JobRow;
+use crate::JobStatus;
+use crate::Params;
+use crate::Payload;
+
+/// How long a claimed job belongs to its worker. A job still `Running` past
+/// its lease is considered abandoned by a dead worker.
+pub const LEASE_SECS: f64 = 300.0;
+
+/// How claims are shared between queues. Queues left out weigh 1 and have no
+/// limit.
+#[derive(Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct QueueShares {
+    /// A queue's share of the jobs claimed among those of the same priority:
+    /// a queue of weight 3 gets 3 for every one a queue of weight 1 gets.
+    pub weights: BTreeMap<String, f64>,
+    /// How many jobs of a queue may be running at once, across the workers
+    /// claiming with that limit.
+    pub limits: BTreeMap<String, i64>,
+}
+
+impl QueueShares {
+    pub fn check(&self) -> Result<(), String> {
+        if let Some((queue, _)) = self.weights.iter().find(|(_, weight)| **weight <= 0.0) {
+            return Err(format!("the weight of queue '{}' must be positive", queue));
+        }
+        if let Some((queue, _)) = self.limits.iter().find(|(_, limit)| **limit < 0) {
+            return Err(format!(
+                "the limit of queue '{}' must not be negative",
+                queue
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Enqueues a job with `executor`, typically the transaction writing what the
+/// job is about: workers only see it once that transaction commits, and it
+/// is gone if that transaction rolls back. What the queue's settings don't
+/// give falls back to the columns' defaults, as with `enqueue`. Returns the
+/// job's id.
+pub async fn enqueue<'e, E>(
+    executor: E,
+    queue: &str,
+    payload: &Payload,
+    metadata: &Value,
+    correlation_id: Option<&str>,
+) -> Result<i64, sqlx::Error>
+where
+    E: Executor<'e, Database = Postgres>,
+{
+    let status = JobStatus::Queued;
+    let payload = json!(payload);
+    logged!(query_scalar!(
+        r#"
+        INSERT INTO jobs (status, payload, metadata, correlation_id, queue, tenant, priority, max_attempts, retry_backoff_secs, timeout_secs)
+        SELECT $1, $2, $3, $4, $5, COALESCE(settings.tenant, 'default'), COALESCE(settings.priority, 0),
+               settings.max_attempts, settings.retry_backoff_secs, settings.timeout_secs
+        FROM (SELECT) AS input
+        LEFT JOIN queue_settings settings ON settings.queue = $5
+        RETURNING id
+        "#,
+        status as JobStatus,
+        payload,
+        metadata,
+        correlation_id,
+        queue,
+    ))
+    .run(|query| query.fetch_one(executor))
+    .await
+}
+
+/// Where the worker gets its jobs from, and reports back to.
+///
+/// Implementations share the same semantics: claiming marks due `Queued` jobs
+/// `Running`, bumps their attempt count and leases them for `LEASE_SECS`;
+/// starting and checkpointing renew the lease; reaping requeues `Running` jobs past their
+/// lease, to be claimed as their next attempt, or fails them for good when
+/// that attempt was their last; releasing requeues claimed jobs right away.
+///
+/// The worker runs on the task that calls it, its futures needn't be `Send`.
+#[allow(async_fn_in_trait)]
+pub trait JobStore {
+    /// Only claims jobs of the given payload types, unless there are none,
+    /// and of at least `min_priority` when set. Higher priorities come first,
+    /// then jobs are shared between queues as `queues` says.
+    async fn claim(
+        &self,
+        batch_size: i64,
+        job_types: &[String],
+        min_priority: Option<i32>,
+        queues: &QueueShares,
+    ) -> Result<Vec<JobRow>, sqlx::Error>;
+
+    /// Returns how many jobs were requeued or failed.
+    async fn reap(&self) -> Result<u64, sqlx::Error>;
+
+    /// Takes `(job_id, attempt)` pairs. Jobs that were never started don't
+    /// count as an attempt. Only applies to jobs still `Running` under their
+    /// attempt, as `finish` does: releasing again changes nothing, and a job
+    /// reaped and claimed again meanwhile is left to its new attempt.
+    async fn release(&self, jobs: &[(i64, i32)], started: bool) -> Result<(), sqlx::Error>;
+
+    /// Only applies to a job still `Running` under `attempt`, and returns
+    /// whether it did: a job reaped and claimed again meanwhile belongs to its
+    /// new attempt, and a job already finished is left as is, so that calling
+    /// this again changes nothing.
+    async fn finish(
+        &self,
+        job_id: i64,
+        attempt: i32,
+        status: JobStatus,
+    ) -> Result<bool, sqlx::Error>;
+
+    /// Records the error, then puts the job back in the queue to run again
+    /// after `retry_in` seconds, or marks it `Failed` for good. Only applies
+    /// to a job still `Running` under `attempt`, as `finish` does.
+    async fn fail(
+        &self,
+        job_id: i64,
+        attempt: i32,
+        error: &JobError,
+        retry_in: Option<f64>,
+    ) -> Result<bool, sqlx::Error>;
+
+    /// Only applies to a job still `Running` under `attempt`, and returns
+    /// whether it did, as `finish` does: a worker that lost the lease must
+    /// neither overwrite the checkpoint of the next attempt nor renew its lease.
+    async fn checkpoint(
+        &self,
+        job_id: i64,
+        attempt: i32,
+        state: serde_json::Value,
+    ) -> Result<bool, sqlx::Error>;
+
+    /// Enqueues follow-up work on behalf of a running job, in its queue and
+    /// with its options: priority, tenant, retry policy and timeout.
+    async fn enqueue_child(
+        &self,
+        parent_job_id: i64,
+        correlation_id: Option<&str>,
+        payload: &Payload,
+        params: Option<&Params>,
+    ) -> Result<i64, sqlx::Error>;
+
+    /// The result of a side effect the job already performed, if any.
+    async fn effect(&self, job_id: i64, key: &str) -> Result<Option<Value>, sqlx::Error>;
+
+    async fn record_effect(&self, job_id: i64, key: &str, result: Value)
+        -> Result<(), sqlx::Error>;
+
+    /// Appends the lines the handler logged during `attempt` to the job's
+    /// events, in order. Recorded whatever the outcome of the attempt.
+    async fn record_logs(
+        &self,
+        job_id: i64,
+        attempt: i32,
+        lines: &[String],
+    ) -> Result<(), sqlx::Error>;
+
+    /// The content of a blob, `None` when there is no such blob.
+    async fn blob(&self, blob_id: i64) -> Result<Option<Vec<u8>>, sqlx::Error>;
+
+    /// Called right before a claimed job is handed to its handler, renews its
+    /// lease: the batch was leased when claimed, and the jobs before this one
+    /// may have taken most of it. Only applies to a job still `Running` under
+    /// `attempt`, and returns whether it did: a job whose lease ran out may
+    /// have been reaped and claimed again already, it must not run twice.
+    async fn start(&self, job_id: i64, attempt: i32) -> Result<bool, sqlx::Error>;
+
+    /// Called once the worker is done with the jobs it claimed last.
+    async fn end_batch(&self) -> Result<(), sqlx::Error> {
+        Ok(())
+    }
+}
+
+/// What `PgJobStore::claim_query` builds, `F` decoding the claimed rows.
+pub type ClaimQuery<'q, F> = Logged<Map<'q, Postgres, F, PgArguments>>;
+
+pub struct PgJobStore {
+    pg_pool: PgPool,
+    /// Set when claims are checked, to tell which worker made them.
+    worker_id: Option<String>,
+}
+
+impl PgJobStore {
+    pub fn new(pg_pool: PgPool) -> Self {
+        PgJobStore {
+            pg_pool,
+            worker_id: None,
+        }
+    }
+
+    /// Records every claim in the `claims` table, and panics when a job gets
+    /// claimed while its previous claim is still open: a canary for locking
+    /// bugs, to run while changing the claim query.
+    pub fn check_claims(mut self, enabled: bool) -> Self {
+        self.worker_id = enabled.then(|| match std::env::var("HOSTNAME") {
+            Ok(host) => format!("{}:{}", host, std::process::id()),
+            Err(_) => std::process::id().to_string(),
+        });
+        self
+    }
+
+    /// The query claiming a batch, which `explain` analyzes too.
+    ///
+    /// Jobs sharing a `concurrency_key` never run at the same time: keys with a
+    /// job already running are skipped, a batch takes at most one job per key,
+    /// and an advisory lock on each key claimed is held until `tx` commits, so
+    /// that concurrent claims can't both take the same key.
+    ///
+    /// The lock is only taken on the jobs kept once the batch is cut and ranked,
+    /// the `CASE` making sure it comes last: taken while looking for jobs, it
+    /// would lock the key of every matching job the sort goes through, keys
+    /// that other workers would then skip until `tx` commits, which is the end
+    /// of the batch with `TxJobStore`. A job whose key another claim locked
+    /// first is left out of the batch.
+    ///
+    /// Claimed rows are locked `FOR NO KEY UPDATE`, which still lets other
+    /// connections insert rows referencing them: with `TxJobStore`, effects are
+    /// recorded outside of the batch transaction, which holds that lock.
+    ///
+    /// Among jobs of the same priority, queues are interleaved by weight: a
+    /// job's turn is its position in its queue divided by the queue's weight,
+    /// `weights` being a JSON object of weights by queue. `limits`, a JSON
+    /// object as well, caps how many jobs of a queue are running once the
+    /// batch is claimed, those already running included.
+    ///
+    /// Without the `wasm` feature, `Wasm` jobs are only claimed when asked for
+    /// by type.
+    pub fn claim_query<'q>(
+        batch_size: i64,
+        job_types: &'q [String],
+        min_priority: Option<i32>,
+        weights: &'q Value,
+        limits: &'q Value,
+    ) -> ClaimQuery<'q, impl FnMut(PgRow) -> Result<JobRow, sqlx::Error> + Send> {
+        let plugins = plugin::ENABLED;
+        logged!(query_as!(
+            JobRow,
+            r#"
+            WITH busy AS (
+                SELECT queue, count(*) AS running
+                FROM jobs
+                WHERE status = 'Running'
+                  AND $7::JSONB ? queue
+                GROUP BY queue
+            ), due AS (
+                SELECT id,
+                       row_number() OVER (PARTITION BY queue ORDER BY priority DESC, id) AS position,
+                       COALESCE(($6::JSONB ->> queue)::FLOAT8, 1) AS weight,
+                       ($7::JSONB ->> queue)::BIGINT - COALESCE((SELECT running FROM busy WHERE busy.queue = jobs.queue), 0) AS room
+                FROM jobs
+                WHERE status = 'Queued'
+                  AND run_at <= now()
+                  AND (cardinality($3::TEXT[]) = 0 OR job_type = ANY($3))
+                  AND ($5 OR cardinality($3::TEXT[]) > 0 OR job_type IS DISTINCT FROM 'Wasm')
+                  AND ($4::INTEGER IS NULL OR priority >= $4)
+                  AND NOT EXISTS (
+                      SELECT 1
+                      FROM circuit_breakers
+                      WHERE circuit_breakers.job_type = jobs.job_type
+                        AND circuit_breakers.open_until > now()
+                  )
+                  AND NOT EXISTS (
+                      SELECT 1
+                      FROM maintenance_windows
+                      WHERE maintenance_windows.queue = jobs.queue
+                        AND now() >= maintenance_windows.opens_at
+                        AND now() < maintenance_windows.closes_at
+                  )
+                  AND NOT EXISTS (
+                      SELECT 1
+                      FROM jobs running
+                      WHERE running.concurrency_key = jobs.concurrency_key
+                        AND running.status = 'Running'
+                  )
+            ), candidates AS (
+                SELECT id, concurrency_key, priority
+                FROM jobs
+                JOIN due USING (id)
+                WHERE status = 'Queued'
+                  AND (room IS NULL OR position <= room)
+                ORDER BY priority DESC, position / weight, id
+                LIMIT $1
+                FOR NO KEY UPDATE OF jobs SKIP LOCKED
+            ), picked AS (
+                SELECT id
+                FROM (
+                    SELECT id, concurrency_key, row_number() OVER (PARTITION BY concurrency_key ORDER BY priority DESC, id) AS rank
+                    FROM candidates
+                ) ranked
+                WHERE CASE
+                    WHEN concurrency_key IS NULL THEN true
+                    WHEN rank > 1 THEN false
+                    ELSE pg_try_advisory_xact_lock(hashtextextended(concurrency_key, 0))
+                END
+            ), claimed AS (
+                UPDATE jobs
+                SET status = 'Running', attempts = attempts + 1, started_at = now(), locked_until = now() + make_interval(secs => $2)
+                WHERE id IN (SELECT id FROM picked)
+                RETURNING *
+            )
+            SELECT id, status AS "status: JobStatus", payload AS "payload: Json<Payload>", params AS "params: Json<Params>", tags, metadata, correlation_id, parent_job_id, checkpoint, attempts, max_attempts, retry_backoff_secs, timeout_secs
+            FROM claimed
+            ORDER BY priority DESC, id
+            "#,
+            batch_size,
+            LEASE_SECS,
+            job_types,
+            min_priority,
+            plugins,
+            weights,
+            limits,
+        ))
+    }
+
+    /// Claims with `claim_query`, within `tx`.
+    ///
+    /// A queue with a limit is only claimed from by one transaction at a time,
+    /// which holds an advisory lock on it until it commits: the claim then
+    /// counts every job running before it. The lock is tried first, in its
+    /// own statement for the claim's snapshot to come after it, and a queue
+    /// locked by another claim is skipped.
+    async fn claim_in(
+        &self,
+        tx: &mut Transaction<'_, Postgres>,
+        batch_size: i64,
+        job_types: &[String],
+        min_priority: Option<i32>,
+        queues: &QueueShares,
+    ) -> Result<Vec<JobRow>, sqlx::Error> {
+        let limited: &[String] = &queues.limits.keys().cloned().collect::<Vec<_>>();
+        let locked = if limited.is_empty() {
+            vec![]
+        } else {
+            logged!(query_scalar!(
+                r#"
+                SELECT queue AS "queue!"
+                FROM UNNEST($1::TEXT[]) AS queue
+                WHERE pg_try_advisory_xact_lock(hashtextextended(queue, 1))
+                "#,
+                limited,
+            ))
+            .run(|query| query.fetch_all(&mut *tx))
+            .await?
+        };
+        let limits: BTreeMap<&String, i64> = queues
+            .limits
+            .iter()
+            .map(|(queue, limit)| (queue, if locked.contains(queue) { *limit } else { 0 }))
+            .collect();
+
+        let weights = json!(queues.weights);
+        let limits = json!(limits);
+        let mut jobs = Self::claim_query(batch_size, job_types, min_priority, &weights, &limits)
+            .run(|query| query.fetch_all(&mut *tx))
+            .await?;
+
+        // A job claimed by another worker just before the lock was taken is
+        // invisible to the claim's snapshot, but not to this later query. The
+        // jobs it finds go back to the queue, as if never claimed.
+        let job_ids: &[i64] = &jobs.iter().map(|job| job.id).collect::<Vec<_>>();
+        let taken = logged!(query_scalar!(
+            r#"
+            SELECT id
+            FROM jobs claimed
+            WHERE id = ANY($1)
+              AND EXISTS (
+                  SELECT 1
+                  FROM jobs running
+                  WHERE running.concurrency_key = claimed.concurrency_key
+                    AND running.status = 'Running'
+                    AND running.id <> ALL($1)
+              )
+            "#,
+            job_ids,
+        ))
+        .run(|query| query.fetch_all(&mut *tx))
+        .await?;
+        if !taken.is_empty() {
+            let released: Vec<(i64, i32)> = jobs
+                .iter()
+                .filter(|job| taken.contains(&job.id))
+                .map(|job| (job.id, job.attempts))
+                .collect();
+            self.release_on(&mut *tx, &released, false).await?;
+            jobs.retain(|job| !taken.contains(&job.id));
+        }
+
+        if let Some(worker_id) = &self.worker_id {
+            self.record_claims(tx, worker_id, &jobs).await?;
+        }
+        Ok(jobs)
+    }
+
+    /// A claim is open until its job leaves `Running`, which a trigger takes
+    /// care of. Recording a claim waits for any concurrent one of the same job
+    /// to commit, so that the race a broken claim query loses is caught.
+    async fn record_claims(
+        &self,
+        tx: &mut Transaction<'_, Postgres>,
+        worker_id: &str,
+        jobs: &[JobRow],
+    ) -> Result<(), sqlx::Error> {
+        let job_ids: &[i64] = &jobs.iter().map(|job| job.id).collect::<Vec<_>>();
+        let attempts: &[i32] = &jobs.iter().map(|job| job.attempts).collect::<Vec<_>>();
+        let recorded: &[i64] = &logged!(query_scalar!(
+            r#"
+            INSERT INTO claims (job_id, worker_id, attempt)
+            SELECT job_id, $2, attempt
+            FROM UNNEST($1::BIGINT[], $3::INTEGER[]) AS claimed(job_id, attempt)
+            ON CONFLICT (job_id) WHERE ended_at IS NULL DO NOTHING
+            RETURNING job_id
+            "#,
+            job_ids,
+            worker_id,
+            attempts,
+        ))
+        .run(|query| query.fetch_all(&mut *tx))
+        .await?;
+        if recorded.len() == job_ids.len() {
+            return Ok(());
+        }
+
+        let duplicates = logged!(query!(
+            r#"
+            SELECT job_id, worker_id, attempt, claimed_at::TEXT AS "claimed_at!"
+            FROM claims
+            WHERE job_id = ANY($1) AND NOT job_id = ANY($2) AND ended_at IS NULL
+            ORDER BY job_id
+            "#,
+            job_ids,
+            recorded,
+        ))
+        .run(|query| query.fetch_all(&mut *tx))
+        .await?;
+        let duplicates: Vec<String> = duplicates
+            .iter()
+            .map(|claim| {
+                format!(
+                    "job #{} was already claimed by worker {} (attempt {}, at {})",
+                    claim.job_id, claim.worker_id, claim.attempt, claim.claimed_at
+                )
+            })
+            .collect();
+        panic!(
+            "duplicate claim by worker {}: {}",
+            worker_id,
+            duplicates.join("; ")
+        );
+    }
+
+    async fn try_claim(
+        &self,
+        batch_size: i64,
+        job_types: &[String],
+        min_priority: Option<i32>,
+        queues: &QueueShares,
+    ) -> Result<Vec<JobRow>, sqlx::Error> {
+        let mut tx = self.pg_pool.begin().await?;
+        let jobs = self
+            .claim_in(&mut tx, batch_size, job_types, min_priority, queues)
+            .await?;
+        tx.commit().await?;
+        Ok(jobs)
+    }
+
+    async fn try_reap(&self) -> Result<u64, sqlx::Error> {
+        let mut tx = self.pg_pool.begin().await?;
+        let reaped = self.reap_in(&mut tx).await?;
+        tx.commit().await?;
+        Ok(reaped)
+    }
+
+    /// The lost attempt is recorded as a timeout. A job failing for good moves
+    /// its workflow forward, as `fail_in` would.
+    async fn reap_in(&self, tx: &mut Transaction<'_, Postgres>) -> Result<u64, sqlx::Error> {
+        let reaped = logged!(query!(
+            r#"
+            UPDATE jobs
+            SET status = CASE WHEN attempts >= COALESCE(max_attempts, $1) THEN 'Failed' ELSE 'Queued' END::JOB_STATUS,
+                finished_at = CASE WHEN attempts >= COALESCE(max_attempts, $1) THEN now() END,
+                locked_until = NULL,
+                last_error = 'lease expired, the worker died or stalled',
+                error_kind = 'Timeout'
+            WHERE status = 'Running'
+              AND locked_until < now()
+            RETURNING id, status AS "status: JobStatus"
+            "#,
+            MAX_ATTEMPTS,
+        ))
+        .run(|query| query.fetch_all(&mut *tx))
+        .await?;
+
+        for job in &reaped {
+            if job.status == JobStatus::Failed {
+                workflow::on_job_finished(tx, job.id, job.status).await?;
+            }
+        }
+        Ok(reaped.len() as u64)
+    }
+
+    /// The whole batch is claimed at once: a job only starts once the ones
+    /// before it are done, which its runtime must not account for, and which
+    /// its lease must not be eaten by.
+    async fn start_on<'e, E>(
+        &self,
+        executor: E,
+        job_id: i64,
+        attempt: i32,
+    ) -> Result<bool, sqlx::Error>
+    where
+        E: Executor<'e, Database = Postgres>,
+    {
+        let started = logged!(query_scalar!(
+            r#"
+            UPDATE jobs
+            SET started_at = clock_timestamp(), locked_until = clock_timestamp() + make_interval(secs => $1)
+            WHERE id = $2
+              AND status = 'Running'
+              AND attempts = $3
+            RETURNING id
+            "#,
+            LEASE_SECS,
+            job_id,
+            attempt,
+        ))
+        .run(|query| query.fetch_optional(executor))
+        .await?;
+        Ok(started.is_some())
+    }
+
+    async fn release_on<'e, E>(
+        &self,
+        executor: E,
+        jobs: &[(i64, i32)],
+        started: bool,
+    ) -> Result<(), sqlx::Error>
+    where
+        E: Executor<'e, Database = Postgres>,
+    {
+        let job_ids: &[i64] = &jobs.iter().map(|(id, _)| *id).collect::<Vec<_>>();
+        let attempts: &[i32] = &jobs.iter().map(|(_, attempt)| *attempt).collect::<Vec<_>>();
+        logged!(query!(
+            r#"
+            UPDATE jobs
+            SET status = 'Queued', locked_until = NULL, attempts = attempts - (CASE WHEN $3 THEN 0 ELSE 1 END)
+            FROM UNNEST($1::BIGINT[], $2::INTEGER[]) AS released(id, attempt)
+            WHERE jobs.id = released.id
+              AND jobs.status = 'Running'
+              AND jobs.attempts = released.attempt
+            "#,
+            job_ids,
+            attempts,
+            started,
+        ))
+        .run(|query| query.execute(executor))
+        .await?;
+        Ok(())
+    }
+
+    async fn try_finish(
+        &self,
+        job_id: i64,
+        attempt: i32,
+        status: JobStatus,
+    ) -> Result<bool, sqlx::Error> {
+        let mut tx = self.pg_pool.begin().await?;
+        let finished = self.finish_in(&mut tx, job_id, attempt, status).await?;
+        tx.commit().await?;
+        Ok(finished)
+    }
+
+    /// Guarded by the job's status and attempt: a retry of a transaction that
+    /// did commit, the connection dying right after, doesn't move the workflow
+    /// forward nor account for the attempt a second time.
+    pub async fn finish_in(
+        &self,
+        tx: &mut Transaction<'_, Postgres>,
+        job_id: i64,
+        attempt: i32,
+        status: JobStatus,
+    ) -> Result<bool, sqlx::Error> {
+        let finished = logged!(query_scalar!(
+            r#"
+            UPDATE jobs
+            SET status = $1, locked_until = NULL, finished_at = now()
+            WHERE id = $2
+              AND status = 'Running'
+              AND attempts = $3
+            RETURNING id
+            "#,
+            status as JobStatus,
+            job_id,
+            attempt,
+        ))
+        .run(|query| query.fetch_optional(&mut *tx))
+        .await?;
+        if finished.is_none() {
+            return Ok(false);
+        }
+
+        usage::on_attempt_ended(tx, job_id).await?;
+        workflow::on_job_finished(tx, job_id, status).await?;
+        Ok(true)
+    }
+
+    async fn try_fail(
+        &self,
+        job_id: i64,
+        attempt: i32,
+        error: &JobError,
+        retry_in: Option<f64>,
+    ) -> Result<bool, sqlx::Error> {
+        let mut tx = self.pg_pool.begin().await?;
+        let failed = self
+            .fail_in(&mut tx, job_id, attempt, error, retry_in)
+            .await?;
+        tx.commit().await?;
+        Ok(failed)
+    }
+
+    /// Guarded like `finish_in`. The first update locks the job's row, the
+    /// ones after it can't find it changed.
+    pub async fn fail_in(
+        &self,
+        tx: &mut Transaction<'_, Postgres>,
+        job_id: i64,
+        attempt: i32,
+        error: &JobError,
+        retry_in: Option<f64>,
+    ) -> Result<bool, sqlx::Error> {
+        let (message, kind) = (&error.message, error.kind);
+        let failed = logged!(query_scalar!(
+            r#"
+            UPDATE jobs
+            SET last_error = $1, error_kind = $2
+            WHERE id = $3
+              AND status = 'Running'
+              AND attempts = $4
+            RETURNING id
+            "#,
+            message,
+            kind as ErrorKind,
+            job_id,
+            attempt,
+        ))
+        .run(|query| query.fetch_optional(&mut *tx))
+        .await?;
+        if failed.is_none() {
+            return Ok(false);
+        }
+
+        match retry_in {
+            Some(retry_in) => {
+                logged!(query!(
+                    r#"
+                    UPDATE jobs
+                    SET status = 'Queued', locked_until = NULL, run_at = now() + make_interval(secs => $1)
+                    WHERE id = $2
+                    "#,
+                    retry_in,
+                    job_id,
+                ))
+                .run(|query| query.execute(&mut *tx))
+                .await?;
+                usage::on_attempt_ended(tx, job_id).await?;
+            }
+            None => {
+                self.finish_in(tx, job_id, attempt, JobStatus::Failed)
+                    .await?;
+            }
+        }
+
+        if error.kind.is_retryable() {
+            breaker::on_job_failed(tx, job_id).await?;
+        }
+        Ok(true)
+    }
+
+    /// Renews the lease of a running job, for workers that don't checkpoint.
+    pub async fn extend_in(
+        &self,
+        tx: &mut Transaction<'_, Postgres>,
+        job_id: i64,
+    ) -> Result<(), sqlx::Error> {
+        logged!(query!(
+            r#"
+            UPDATE jobs
+            SET locked_until = now() + make_interval(secs => $1)
+            WHERE id = $2
+            "#,
+            LEASE_SECS,
+            job_id,
+        ))
+        .run(|query| query.execute(&mut *tx))
+        .await?;
+        Ok(())
+    }
+
+    async fn checkpoint_on<'e, E>(
+        &self,
+        executor: E,
+        job_id: i64,
+        attempt: i32,
+        state: serde_json::Value,
+    ) -> Result<bool, sqlx::Error>
+    where
+        E: Executor<'e, Database = Postgres>,
+    {
+        let checkpointed = logged!(query_scalar!(
+            r#"
+            UPDATE jobs
+            SET checkpoint = $1, locked_until = clock_timestamp() + make_interval(secs => $2)
+            WHERE id = $3
+              AND status = 'Running'
+              AND attempts = $4
+            RETURNING id
+            "#,
+            state,
+            LEASE_SECS,
+            job_id,
+            attempt,
+        ))
+        .run(|query| query.fetch_optional(executor))
+        .await?;
+        Ok(checkpointed.is_some())
+    }
+
+    async fn enqueue_child_on<'e, E>(
+        &self,
+        executor: E,
+        parent_job_id: i64,
+        correlation_id: Option<&str>,
+        payload: &Payload,
+        params: Option<&Params>,
+    ) -> Result<i64, sqlx::Error>
+    where
+        E: Executor<'e, Database = Postgres>,
+    {
+        let status = JobStatus::Queued;
+        let payload = json!(payload);
+        let params = params.map(|params| json!(params));
+        logged!(query_scalar!(
+            r#"
+            INSERT INTO jobs (status, payload, params, correlation_id, parent_job_id, queue, tenant, priority, max_attempts, retry_backoff_secs, timeout_secs)
+            SELECT $1, $2, $3, $4, $5, queue, tenant, priority, max_attempts, retry_backoff_secs, timeout_secs
+            FROM jobs
+            WHERE id = $5
+            RETURNING id
+            "#,
+            status as JobStatus,
+            payload,
+            params,
+            correlation_id,
+            parent_job_id,
+        ))
+        .run(|query| query.fetch_one(executor))
+        .await
+    }
+}
+
+impl JobStore for PgJobStore {
+    async fn claim(
+        &self,
+        batch_size: i64,
+        job_types: &[String],
+        min_priority: Option<i32>,
+        queues: &QueueShares,
+    ) -> Result<Vec<JobRow>, sqlx::Error> {
+        with_retry("claim", || {
+            self.try_claim(batch_size, job_types, min_priority, queues)
+        })
+        .await
+    }
+
+    /// Jobs marked `Running` outside of the worker (i.e. by the demo) carry no
+    /// lease and are left alone.
+    async fn reap(&self) -> Result<u64, sqlx::Error> {
+        with_retry("reap", || self.try_reap()).await
+    }
+
+    async fn release(&self, jobs: &[(i64, i32)], started: bool) -> Result<(), sqlx::Error> {
+        with_retry("release", || self.release_on(&self.pg_pool, jobs, started)).await
+    }
+
+    /// Also moves the job's workflow forward, in the same transaction.
+    async fn finish(
+        &self,
+        job_id: i64,
+        attempt: i32,
+        status: JobStatus,
+    ) -> Result<bool, sqlx::Error> {
+        with_retry("finish", || self.try_finish(job_id, attempt, status)).await
+    }
+
+    /// A final failure moves the job's workflow forward, in the same transaction.
+    async fn fail(
+        &self,
+        job_id: i64,
+        attempt: i32,
+        error: &JobError,
+        retry_in: Option<f64>,
+    ) -> Result<bool, sqlx::Error> {
+        with_retry("fail", || self.try_fail(job_id, attempt, error, retry_in)).await
+    }
+
+    async fn checkpoint(
+        &self,
+        job_id: i64,
+        attempt: i32,
+        state: serde_json::Value,
+    ) -> Result<bool, sqlx::Error> {
+        self.checkpoint_on(&self.pg_pool, job_id, attempt, state)
+            .await
+    }
+
+    async fn enqueue_child(
+        &self,
+        parent_job_id: i64,
+        correlation_id: Option<&str>,
+        payload: &Payload,
+        params: Option<&Params>,
+    ) -> Result<i64, sqlx::Error> {
+        self.enqueue_child_on(
+            &self.pg_pool,
+            parent_job_id,
+            correlation_id,
+            payload,
+            params,
+        )
+        .await
+    }
+
+    async fn effect(&self, job_id: i64, key: &str) -> Result<Option<Value>, sqlx::Error> {
+        logged!(query_scalar!(
+            "SELECT result FROM effects WHERE job_id = $1 AND key = $2",
+            job_id,
+            key,
+        ))
+        .run(|query| query.fetch_optional(&self.pg_pool))
+        .await
+    }
+
+    async fn record_effect(
+        &self,
+        job_id: i64,
+        key: &str,
+        result: Value,
+    ) -> Result<(), sqlx::Error> {
+        logged!(query!(
+            r#"
+            INSERT INTO effects (job_id, key, result)
+            VALUES ($1, $2, $3)
+            ON CONFLICT (job_id, key) DO NOTHING
+            "#,
+            job_id,
+            key,
+            result,
+        ))
+        .run(|query| query.execute(&self.pg_pool))
+        .await?;
+        Ok(())
+    }
+
+    async fn blob(&self, blob_id: i64) -> Result<Option<Vec<u8>>, sqlx::Error> {
+        logged!(query_scalar!(
+            "SELECT content FROM blobs WHERE id = $1",
+            blob_id
+        ))
+        .run(|query| query.fetch_optional(&self.pg_pool))
+        .await
+    }
+
+    async fn record_logs(
+        &self,
+        job_id: i64,
+        attempt: i32,
+        lines: &[String],
+    ) -> Result<(), sqlx::Error> {
+        if lines.is_empty() {
+            return Ok(());
+        }
+        logged!(query!(
+            r#"
+            INSERT INTO job_events (job_id, attempt, kind, message)
+            SELECT $1, $2, 'log', input.line
+            FROM UNNEST($3::TEXT[]) WITH ORDINALITY AS input(line, position)
+            ORDER BY input.position
+            "#,
+            job_id,
+            attempt,
+            lines,
+        ))
+        .run(|query| query.execute(&self.pg_pool))
+        .await?;
+        Ok(())
+    }
+
+    async fn start(&self, job_id: i64, attempt: i32) -> Result<bool, sqlx::Error> {
+        with_retry("start", || self.start_on(&self.pg_pool, job_id, attempt)).await
+    }
+}
+
+/// Runs each batch within a single transaction, committed once the worker is
+/// done with the batch, each job getting its own savepoint: a failed job only
+/// rolls back its own effects (follow-up jobs, checkpoints...) before being
+/// marked `Failed`, the rest of the batch commits.
+///
+/// Claimed rows stay locked until the commit, and the whole batch goes back to
+/// `Queued` should the worker die, done jobs included.
+pub struct TxJobStore<'a> {
+    store: &'a PgJobStore,
+    tx: Mutex<Option<Transaction<'static, Postgres>>>,
+}
+
+impl<'a> TxJobStore<'a> {
+    pub fn new(store: &'a PgJobStore) -> Self {
+        TxJobStore {
+            store,
+            tx: Mutex::new(None),
+        }
+    }
+
+    /// The transaction of the current batch, begun on first use.
+    async fn tx(
+        &self,
+    ) -> Result<MutexGuard<'_, Option<Transaction<'static, Postgres>>>, sqlx::Error> {
+        let mut tx = self.tx.lock().await;
+        if tx.is_none() {
+            *tx = Some(self.store.pg_pool.begin().await?);
+        }
+        Ok(tx)
+    }
+
+    /// Undoes everything done since the current job started.
+    async fn rollback_job(tx: &mut Transaction<'static, Postgres>) -> Result<(), sqlx::Error> {
+        logged!(query("ROLLBACK TO SAVEPOINT job"))
+            .run(|query| query.execute(&mut *tx))
+            .await?;
+        logged!(query("RELEASE SAVEPOINT job"))
+            .run(|query| query.execute(&mut *tx))
+            .await?;
+        Ok(())
+    }
+}
+
+// No retries here: an error aborts the transaction, replaying the query alone
+// would not help.
+impl JobStore for TxJobStore<'_> {
+    async fn claim(
+        &self,
+        batch_size: i64,
+        job_types: &[String],
+        min_priority: Option<i32>,
+        queues: &QueueShares,
+    ) -> Result<Vec<JobRow>, sqlx::Error> {
+        let mut tx = self.tx().await?;
+        let tx = tx.as_mut().expect("transaction begun");
+        self.store
+            .claim_in(tx, batch_size, job_types, min_priority, queues)
+            .await
+    }
+
+    async fn reap(&self) -> Result<u64, sqlx::Error> {
+        let mut tx = self.tx().await?;
+        let tx = tx.as_mut().expect("transaction begun");
+        self.store.reap_in(tx).await
+    }
+
+    /// A started job is rolled back to its savepoint first.
+    async fn release(&self, jobs: &[(i64, i32)], started: bool) -> Result<(), sqlx::Error> {
+        let mut tx = self.tx().await?;
+        let tx = tx.as_mut().expect("transaction begun");
+        if started {
+            Self::rollback_job(tx).await?;
+        }
+        self.store.release_on(&mut *tx, jobs, started).await
+    }
+
+    async fn finish(
+        &self,
+        job_id: i64,
+        attempt: i32,
+        status: JobStatus,
+    ) -> Result<bool, sqlx::Error> {
+        let mut tx = self.tx().await?;
+        let tx = tx.as_mut().expect("transaction begun");
+        if status == JobStatus::Done {
+            logged!(query("RELEASE SAVEPOINT job"))
+                .run(|query| query.execute(&mut *tx))
+                .await?;
+        } else {
+            Self::rollback_job(tx).await?;
+        }
+        self.store.finish_in(tx, job_id, attempt, status).await
+    }
+
+    async fn fail(
+        &self,
+        job_id: i64,
+        attempt: i32,
+        error: &JobError,
+        retry_in: Option<f64>,
+    ) -> Result<bool, sqlx::Error> {
+        let mut tx = self.tx().await?;
+        let tx = tx.as_mut().expect("transaction begun");
+        Self::rollback_job(tx).await?;
+        self.store
+            .fail_in(tx, job_id, attempt, error, retry_in)
+            .await
+    }
+
+    async fn checkpoint(
+        &self,
+        job_id: i64,
+        attempt: i32,
+        state: serde_json::Value,
+    ) -> Result<bool, sqlx::Error> {
+        let mut tx = self.tx().await?;
+        let tx = tx.as_mut().expect("transaction begun");
+        self.store
+            .checkpoint_on(&mut *tx, job_id, attempt, state)
+            .await
+    }
+
+    async fn enqueue_child(
+        &self,
+        parent_job_id: i64,
+        correlation_id: Option<&str>,
+        payload: &Payload,
+        params: Option<&Params>,
+    ) -> Result<i64, sqlx::Error> {
+        let mut tx = self.tx().await?;
+        let tx = tx.as_mut().expect("transaction begun");
+        self.store
+            .enqueue_child_on(&mut *tx, parent_job_id, correlation_id, payload, params)
+            .await
+    }
+
+    /// Effects are recorded outside of the transaction: they happened, even
+    /// if the job gets rolled back.
+    async fn effect(&self, job_id: i64, key: &str) -> Result<Option<Value>, sqlx::Error> {
+        self.store.effect(job_id, key).await
+    }
+
+    async fn record_effect(
+        &self,
+        job_id: i64,
+        key: &str,
+        result: Value,
+    ) -> Result<(), sqlx::Error> {
+        self.store.record_effect(job_id, key, result).await
+    }
+
+    async fn blob(&self, blob_id: i64) -> Result<Option<Vec<u8>>, sqlx::Error> {
+        self.store.blob(blob_id).await
+    }
+
+    /// Outside of the batch's transaction, as effects: the logs of a failed
+    /// attempt are kept, when its changes are rolled back.
+    async fn record_logs(
+        &self,
+        job_id: i64,
+        attempt: i32,
+        lines: &[String],
+    ) -> Result<(), sqlx::Error> {
+        self.store.record_logs(job_id, attempt, lines).await
+    }
+
+    /// The start time is set before the savepoint, so that it survives a
+    /// failure: the failed attempt's runtime is still accounted for.
+    async fn start(&self, job_id: i64, attempt: i32) -> Result<bool, sqlx::Error> {
+        let mut tx = self.tx().await?;
+        let tx = tx.as_mut().expect("transaction begun");
+        if !self.store.start_on(&mut *tx, job_id, attempt).await? {
+            return Ok(false);
+        }
+        logged!(query("SAVEPOINT job"))
+            .run(|query| query.execute(&mut *tx))
+            .await?;
+        Ok(true)
+    }
+
+    async fn end_batch(&self) -> Result<(), sqlx::Error> {
+        match self.tx.lock().await.take() {
+            Some(tx) => tx.commit().await,
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Claims within a transaction rolled back afterwards. Test jobs get a
+    /// priority no other job has, which the claim asks for.
+    const PRIORITY: i32 = 1_000_000;
+
+    async fn enqueue(tx: &mut Transaction<'_, Postgres>, queue: &str, status: &str, count: i32) {
+        sqlx::query(
+            r#"
+            INSERT INTO jobs (payload, queue, status, priority)
+            SELECT '{"type": "NOOP"}', $1, $2::JOB_STATUS, $3
+            FROM generate_series(1, $4)
+            "#,
+        )
+        .bind(queue)
+        .bind(status)
+        .bind(PRIORITY)
+        .bind(count)
+        .execute(&mut *tx)
+        .await
+        .unwrap();
+    }
+
+    async fn claimed_queues(
+        tx: &mut Transaction<'_, Postgres>,
+        batch_size: i64,
+        queues: &QueueShares,
+    ) -> Vec<String> {
+        let store = PgJobStore::new(crate::must_get_pool().await);
+        let jobs = store
+            .claim_in(tx, batch_size, &[], Some(PRIORITY), queues)
+            .await
+            .unwrap();
+        let job_ids: Vec<i64> = jobs.iter().map(|job| job.id).collect();
+        sqlx::query_scalar("SELECT queue FROM jobs WHERE id = ANY($1) ORDER BY queue")
+            .bind(job_ids)
+            .fetch_all(&mut *tx)
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn claim_shares_batches_by_queue_weight() {
+        let pg_pool = crate::must_get_pool().await;
+        let mut tx = pg_pool.begin().await.unwrap();
+        enqueue(&mut tx, "test-heavy", "Queued", 4).await;
+        enqueue(&mut tx, "test-light", "Queued", 4).await;
+
+        let queues = QueueShares {
+            weights: BTreeMap::from([("test-heavy".to_string(), 3.0)]),
+            ..QueueShares::default()
+        };
+        let claimed = claimed_queues(&mut tx, 4, &queues).await;
+        assert_eq!(
+            claimed,
+            vec!["test-heavy", "test-heavy", "test-heavy", "test-light"]
+        );
+    }
+
+    #[tokio::test]
+    async fn claim_counts_running_jobs_against_queue_limits() {
+        let pg_pool = crate::must_get_pool().await;
+        let mut tx = pg_pool.begin().await.unwrap();
+        enqueue(&mut tx, "test-limited", "Running", 1).await;
+        enqueue(&mut tx, "test-limited", "Queued", 3).await;
+        enqueue(&mut tx, "test-other", "Queued", 1).await;
+
+        let queues = QueueShares {
+            limits: BTreeMap::from([("test-limited".to_string(), 2)]),
+            ..QueueShares::default()
+        };
+        let claimed = claimed_queues(&mut tx, 5, &queues).await;
+        assert_eq!(claimed, vec!["test-limited", "test-other"]);
+    }
+}
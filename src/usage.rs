@@ -0,0 +1,63 @@
+use serde::Serialize;
+use sqlx::PgPool;
+use sqlx::Postgres;
+use sqlx::Transaction;
+
+use crate::query_log::logged;
+
+/// Runtime spent on the jobs of one tenant and payload type.
+#[derive(Serialize, Debug)]
+pub struct Usage {
+    pub tenant: String,
+    pub job_type: String,
+    pub attempts: i64,
+    pub runtime_seconds: f64,
+}
+
+/// Accounts for the attempt of the job that just ended, from its `started_at`
+/// to now. Failed attempts count as much as successful ones: they cost the
+/// same to run.
+///
+/// Usage rows are only ever inserted: updating one row per tenant would have
+/// every worker wait on it, for a whole batch with `TxJobStore`.
+pub async fn on_attempt_ended(
+    tx: &mut Transaction<'_, Postgres>,
+    job_id: i64,
+) -> Result<(), sqlx::Error> {
+    logged!(query!(
+        r#"
+        INSERT INTO usage (job_id, tenant, job_type, attempt, runtime_seconds)
+        SELECT id, tenant, COALESCE(job_type, 'unknown'), attempts, EXTRACT(EPOCH FROM clock_timestamp() - started_at)::FLOAT8
+        FROM jobs
+        WHERE id = $1
+          AND started_at IS NOT NULL
+        "#,
+        job_id,
+    ))
+    .run(|query| query.execute(&mut *tx))
+    .await?;
+    Ok(())
+}
+
+/// Usage over the last `hours`, heaviest first, optionally for a single tenant.
+pub async fn fetch(
+    pg_pool: &PgPool,
+    hours: i32,
+    tenant: Option<&str>,
+) -> Result<Vec<Usage>, sqlx::Error> {
+    sqlx::query_as!(
+        Usage,
+        r#"
+        SELECT tenant, job_type, count(*) AS "attempts!", sum(runtime_seconds) AS "runtime_seconds!"
+        FROM usage
+        WHERE recorded_at > now() - make_interval(hours => $1)
+          AND ($2::TEXT IS NULL OR tenant = $2)
+        GROUP BY tenant, job_type
+        ORDER BY sum(runtime_seconds) DESC, tenant, job_type
+        "#,
+        hours,
+        tenant,
+    )
+    .fetch_all(pg_pool)
+    .await
+}
@@ -0,0 +1,781 @@
+use std::any::Any;
+use std::cell::Cell;
+use std::cell::RefCell;
+use std::future::Future;
+use std::panic::AssertUnwindSafe;
+use std::pin::Pin;
+use std::task::Context;
+use std::task::Poll;
+use std::time::Duration;
+use std::time::Instant;
+
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde::Serialize;
+use serde_json::json;
+use serde_json::Value;
+use tokio::sync::watch;
+
+use crate::blob;
+use crate::blob::Attachment;
+use crate::config::WorkerConfig;
+use crate::email;
+use crate::email::Email;
+use crate::email::Template;
+use crate::error::JobError;
+use crate::plugin;
+use crate::shutdown;
+use crate::shutdown::Shutdown;
+use crate::store::JobStore;
+use crate::store::LEASE_SECS;
+use crate::JobStatus;
+use crate::Params;
+use crate::Payload;
+
+/// What a handler knows about the job it is working on.
+///
+/// Delivery is at-least-once: when a worker dies mid-job, the job stays
+/// `Running` until its lease expires, then the reaper puts it back in the
+/// queue. The next run sees `attempt() > 1`, along with the latest checkpoint,
+/// and should expect any effect before that checkpoint to have happened
+/// already. Failed jobs put back with `retry` follow the same rules.
+struct JobContext<'a, S> {
+    store: &'a S,
+    job_id: i64,
+    correlation_id: Option<String>,
+    checkpoint: Option<serde_json::Value>,
+    attempt: i32,
+    lease_end: Cell<Instant>,
+    /// When the job has run for its `timeout_secs`, if it has one.
+    timeout_at: Option<Instant>,
+    /// The lines logged so far, recorded with the job once the attempt ends.
+    logs: RefCell<Vec<String>>,
+}
+
+/// When a lease taken or renewed at `from` runs out.
+fn lease_end(from: Instant) -> Instant {
+    from + Duration::from_secs_f64(LEASE_SECS)
+}
+
+impl<S: JobStore> JobContext<'_, S> {
+    /// Every line logged by a handler carries the job id and its correlation
+    /// id, which is what ties a chain of jobs back to the originating request.
+    fn log(&self, message: &str) {
+        match &self.correlation_id {
+            Some(correlation_id) => println!(
+                "   [job #{} correlation_id={}] {}",
+                self.job_id, correlation_id, message
+            ),
+            None => println!("   [job #{}] {}", self.job_id, message),
+        }
+        self.logs.borrow_mut().push(message.to_string());
+    }
+
+    /// Records the lines logged since the last call with the job.
+    async fn record_logs(&self) -> Result<(), sqlx::Error> {
+        let lines = self.logs.take();
+        self.store
+            .record_logs(self.job_id, self.attempt(), &lines)
+            .await
+    }
+
+    /// Enqueues follow-up work as a child of the current job, propagating its
+    /// correlation id.
+    async fn enqueue(
+        &self,
+        payload: &Payload,
+        params: Option<&Params>,
+    ) -> Result<i64, sqlx::Error> {
+        self.store
+            .enqueue_child(self.job_id, self.correlation_id.as_deref(), payload, params)
+            .await
+    }
+
+    /// Starts at 1, and is bumped every time the job gets claimed.
+    fn attempt(&self) -> i32 {
+        self.attempt
+    }
+
+    fn is_retry(&self) -> bool {
+        self.attempt > 1
+    }
+
+    /// When the job's lease runs out, pushed back by every checkpoint, or when
+    /// it times out, whichever comes first. Past the former, the job may be
+    /// reaped and run again by another worker; past the latter, it is failed:
+    /// outbound calls shouldn't be allowed to last longer.
+    fn deadline(&self) -> Instant {
+        match self.timeout_at {
+            Some(timeout_at) => self.lease_end.get().min(timeout_at),
+            None => self.lease_end.get(),
+        }
+    }
+
+    /// What is left until the deadline, zero once it has passed.
+    fn time_remaining(&self) -> Duration {
+        self.deadline().saturating_duration_since(Instant::now())
+    }
+
+    /// Persists the progress made so far, a retry of this job will be handed
+    /// the latest checkpoint instead of starting over. Checkpointing also
+    /// renews the lease, so long-running jobs aren't reaped while progressing.
+    /// It doesn't push the deadline past the job's timeout.
+    ///
+    /// Fails once the lease is lost: the job was reaped, and maybe claimed
+    /// again, so going on would only repeat what its next attempt does.
+    async fn checkpoint(&self, state: impl Serialize) -> Result<(), JobError> {
+        let renewed_at = Instant::now();
+        if !self
+            .store
+            .checkpoint(self.job_id, self.attempt, json!(state))
+            .await?
+        {
+            return Err(JobError::timeout(
+                "lease lost: the job is no longer running under this attempt",
+            ));
+        }
+        self.lease_end.set(lease_end(renewed_at));
+        Ok(())
+    }
+
+    /// Runs a side effect at most once per job, as far as the store knows: the
+    /// result is recorded under `key` and handed back to retries instead of
+    /// running `effect` again. A worker dying between the effect and its
+    /// recording still runs it twice, the window is just much smaller. A
+    /// failed effect isn't recorded, retries run it again.
+    async fn run_once<T, E, F>(&self, key: &str, effect: F) -> Result<T, E>
+    where
+        T: Serialize + DeserializeOwned,
+        E: From<sqlx::Error>,
+        F: Future<Output = Result<T, E>>,
+    {
+        if let Some(result) = self.store.effect(self.job_id, key).await? {
+            self.log(&format!("'{}' already done, skipping", key));
+            return serde_json::from_value(result)
+                .map_err(|err| sqlx::Error::Decode(err.into()).into());
+        }
+        let result = effect.await?;
+        self.store
+            .record_effect(self.job_id, key, json!(result))
+            .await?;
+        Ok(result)
+    }
+
+    /// The checkpoint saved by a previous run of this job, if any.
+    fn last_checkpoint<T: DeserializeOwned>(&self) -> Result<Option<T>, sqlx::Error> {
+        match &self.checkpoint {
+            Some(checkpoint) => serde_json::from_value(checkpoint.clone())
+                .map(Some)
+                .map_err(|err| sqlx::Error::Decode(err.into())),
+            None => Ok(None),
+        }
+    }
+
+    /// The content of an attachment, once checked against the checksum taken
+    /// at enqueue time. A missing or altered blob won't come back by itself,
+    /// the job fails for good.
+    async fn attachment(&self, attachment: &Attachment) -> Result<Vec<u8>, JobError> {
+        let content = self.store.blob(attachment.blob_id).await?.ok_or_else(|| {
+            JobError::permanent(format!(
+                "attachment {} is missing: no blob #{}",
+                attachment.filename, attachment.blob_id
+            ))
+        })?;
+        blob::verify(attachment, &content).map_err(JobError::permanent)?;
+        Ok(content)
+    }
+}
+
+/// How far a `SendEmailBatch` job went.
+#[derive(Serialize, Deserialize)]
+struct BatchProgress {
+    sent: usize,
+}
+
+const CHECKPOINT_EVERY: usize = 100;
+
+/// How long the mail server gets to accept an email, at most.
+const SMTP_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Retryable failures are retried until the job has run that many times,
+/// unless it was enqueued with its own limit. Abandoned jobs too.
+pub const MAX_ATTEMPTS: i32 = 5;
+
+/// Delay before the first retry, doubled on every later one, unless the job
+/// was enqueued with its own.
+const RETRY_BACKOFF_SECS: f64 = 10.0;
+
+/// How a job's retryable failures are retried.
+pub struct RetryPolicy {
+    max_attempts: i32,
+    backoff_secs: f64,
+}
+
+impl RetryPolicy {
+    /// The job's own settings, the worker's defaults for those it has none of.
+    pub fn new(max_attempts: Option<i32>, backoff_secs: Option<f64>) -> Self {
+        RetryPolicy {
+            max_attempts: max_attempts.unwrap_or(MAX_ATTEMPTS),
+            backoff_secs: backoff_secs.unwrap_or(RETRY_BACKOFF_SECS),
+        }
+    }
+}
+
+/// When to run a failed job again, `None` meaning never.
+pub fn retry_in(error: &JobError, attempt: i32, policy: &RetryPolicy) -> Option<f64> {
+    (error.kind.is_retryable() && attempt < policy.max_attempts)
+        .then(|| policy.backoff_secs * 2f64.powi(attempt - 1))
+}
+
+/// Fails the job as timed out once it has run for `timeout_secs`. The lease
+/// still applies: a job timing out after it must checkpoint to get that far.
+async fn with_timeout(
+    timeout_secs: Option<f64>,
+    handled: impl Future<Output = Result<(), JobError>>,
+) -> Result<(), JobError> {
+    match timeout_secs {
+        Some(secs) => tokio::time::timeout(Duration::from_secs_f64(secs), handled)
+            .await
+            .unwrap_or_else(|_| Err(JobError::timeout(format!("timed out after {}s", secs)))),
+        None => handled.await,
+    }
+}
+
+/// A template missing from this worker may be deployed by the next attempt,
+/// any other rendering error would fail the same way again.
+fn render(template: &Template) -> Result<Email, JobError> {
+    email::render(template).map_err(|err| {
+        let message = format!("cannot render template '{}': {}", template.name, err);
+        match err.kind() {
+            minijinja::ErrorKind::TemplateNotFound => JobError::retryable(message),
+            _ => JobError::permanent(message),
+        }
+    })
+}
+
+async fn handle<S: JobStore>(
+    ctx: &JobContext<'_, S>,
+    payload: &Payload,
+    params: Option<&Params>,
+) -> Result<(), JobError> {
+    match payload {
+        Payload::NOOP => ctx.log("NOOP!"),
+        Payload::SendEmail {
+            email,
+            template,
+            attachments,
+        } => {
+            if !email.contains('@') {
+                return Err(JobError::permanent(format!(
+                    "invalid email address: {}",
+                    email
+                )));
+            }
+            if ctx.is_retry() {
+                ctx.log(&format!("attempt #{}", ctx.attempt()));
+            }
+            // Rendered and fetched before anything is sent: a broken template
+            // or attachment fails the job without side effects.
+            let rendered = template.as_ref().map(render).transpose()?;
+            let mut files = vec![];
+            for attachment in attachments {
+                files.push((&attachment.filename, ctx.attachment(attachment).await?));
+            }
+            let timeout = ctx.time_remaining().min(SMTP_TIMEOUT);
+            tokio::time::timeout(
+                timeout,
+                ctx.run_once("send", async {
+                    match &rendered {
+                        Some(rendered) => {
+                            ctx.log(&format!(
+                                "EMAIL[{}] {}",
+                                email.to_ascii_uppercase(),
+                                rendered.subject
+                            ));
+                            for line in rendered.body.lines() {
+                                ctx.log(&format!("  | {}", line));
+                            }
+                        }
+                        None => ctx.log(&format!("EMAIL[{}]", email.to_ascii_uppercase())),
+                    }
+                    for (filename, content) in &files {
+                        ctx.log(&format!("  + {} ({} bytes)", filename, content.len()));
+                    }
+                    Ok::<_, sqlx::Error>(())
+                }),
+            )
+            .await
+            .map_err(|_| {
+                JobError::timeout(format!(
+                    "no answer from the mail server within {:?}",
+                    timeout
+                ))
+            })??;
+
+            if let Some(Params::FollowUp(true)) = params {
+                let follow_up = Payload::SendEmail {
+                    email: email.clone(),
+                    template: None,
+                    attachments: vec![],
+                };
+                let id = ctx
+                    .run_once("follow-up", ctx.enqueue(&follow_up, None))
+                    .await?;
+                ctx.log(&format!("enqueued follow-up job #{}", id));
+            }
+        }
+        Payload::SendEmailBatch { emails } => {
+            let mut progress = ctx.last_checkpoint()?.unwrap_or(BatchProgress { sent: 0 });
+            if progress.sent > 0 {
+                ctx.log(&format!("resuming after {} emails", progress.sent));
+            }
+
+            for email in emails.iter().skip(progress.sent) {
+                ctx.run_once(&format!("send:{}", progress.sent), async {
+                    ctx.log(&format!("EMAIL[{}]", email.to_ascii_uppercase()));
+                    Ok::<_, sqlx::Error>(())
+                })
+                .await?;
+                progress.sent += 1;
+                if progress.sent % CHECKPOINT_EVERY == 0 {
+                    ctx.checkpoint(&progress).await?;
+                }
+            }
+        }
+        Payload::Wasm { module, input } => {
+            // Recorded like any effect: once the plugin succeeded, a retry
+            // gets its output back instead of running it again.
+            let output = ctx
+                .run_once("wasm", async {
+                    let outcome = {
+                        let module = module.clone();
+                        let input = serde_json::to_vec(input).expect("JSON always serializes");
+                        // Plugins run synchronously, off the async threads.
+                        tokio::task::spawn_blocking(move || plugin::run(&module, &input))
+                            .await
+                            .map_err(|err| match err.try_into_panic() {
+                                Ok(panic) => JobError::panic(panic),
+                                // The runtime is shutting down.
+                                Err(_) => JobError::retryable("the plugin was cancelled"),
+                            })?
+                    };
+                    for line in &outcome.logs {
+                        ctx.log(&format!("  > {}", line));
+                    }
+                    let output = outcome.result?;
+                    Ok::<_, JobError>(serde_json::from_slice(&output).unwrap_or_else(|_| {
+                        Value::String(String::from_utf8_lossy(&output).into_owned())
+                    }))
+                })
+                .await?;
+            ctx.log(&format!("WASM[{}] -> {}", module, output));
+        }
+    }
+    Ok(())
+}
+
+/// Resolves to `Err` with the panic payload when polling the inner future
+/// panics, instead of unwinding through the worker loop.
+struct CatchUnwind<F>(Pin<Box<F>>);
+
+impl<F: Future> Future for CatchUnwind<F> {
+    type Output = Result<F::Output, Box<dyn Any + Send>>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let inner = self.0.as_mut();
+        match std::panic::catch_unwind(AssertUnwindSafe(|| inner.poll(cx))) {
+            Ok(Poll::Pending) => Poll::Pending,
+            Ok(Poll::Ready(output)) => Poll::Ready(Ok(output)),
+            Err(panic) => Poll::Ready(Err(panic)),
+        }
+    }
+}
+
+/// Claims and handles batches of due jobs until there are none left, or until
+/// asked to stop when polling for new jobs. The config is read anew before
+/// every claim, so that changes apply without a restart.
+pub async fn run<S: JobStore>(store: &S, config: watch::Receiver<WorkerConfig>) {
+    run_until(store, config, shutdown::listen_for_signals()).await
+}
+
+/// `run`, stopping as `shutdown` asks rather than on signals.
+async fn run_until<S: JobStore>(
+    store: &S,
+    config: watch::Receiver<WorkerConfig>,
+    shutdown: watch::Receiver<Shutdown>,
+) {
+    while *shutdown.borrow() == Shutdown::Not {
+        let (batch_size, poll, job_types, min_priority, queues) = {
+            let config = config.borrow();
+            (
+                config.batch_size,
+                config.poll(),
+                config.job_types.clone(),
+                config.min_priority,
+                config.queues.clone(),
+            )
+        };
+
+        let reaped = store.reap().await.expect("failed to reap jobs!");
+        if reaped > 0 {
+            println!("Reaped {} abandoned job(s)", reaped);
+        }
+
+        let jobs = store
+            .claim(batch_size, &job_types, min_priority, &queues)
+            .await
+            .expect("failed to claim jobs!");
+
+        if jobs.is_empty() {
+            store.end_batch().await.expect("could not end the batch");
+            match poll {
+                Some(poll) => {
+                    tokio::select! {
+                        _ = tokio::time::sleep(poll) => {}
+                        _ = shutdown::requested(shutdown.clone(), Shutdown::Soft) => {}
+                    }
+                    continue;
+                }
+                None => {
+                    println!("No more due jobs.");
+                    return;
+                }
+            }
+        }
+
+        let mut jobs = jobs.into_iter();
+        while let Some(job) = jobs.next() {
+            if *shutdown.borrow() != Shutdown::Not {
+                let unstarted: Vec<(i64, i32)> = std::iter::once(job)
+                    .chain(jobs.by_ref())
+                    .map(|job| (job.id, job.attempts))
+                    .collect();
+                store
+                    .release(&unstarted, false)
+                    .await
+                    .expect("could not release jobs");
+                println!("Released {} unstarted job(s)", unstarted.len());
+                break;
+            }
+
+            println!(
+                "Working on job #{} -> {:?} | {:?}",
+                job.id, job.payload.0, job.params
+            );
+
+            // The lease is renewed from some point after this, a deadline
+            // computed from it errs on the early side.
+            let started = Instant::now();
+            if !store
+                .start(job.id, job.attempts)
+                .await
+                .expect("could not start the job")
+            {
+                println!(
+                    "   [job #{}] skipped: its lease ran out before it started, it may be running elsewhere",
+                    job.id
+                );
+                continue;
+            }
+            let ctx = JobContext {
+                store,
+                job_id: job.id,
+                correlation_id: job.correlation_id,
+                checkpoint: job.checkpoint,
+                attempt: job.attempts,
+                lease_end: Cell::new(lease_end(started)),
+                timeout_at: job
+                    .timeout_secs
+                    .map(|secs| started + Duration::from_secs_f64(secs)),
+                logs: RefCell::new(vec![]),
+            };
+            let params = job.params.as_ref().map(|params| &params.0);
+            let result = tokio::select! {
+                // A panicking handler only fails its own job.
+                result = CatchUnwind(Box::pin(with_timeout(job.timeout_secs, handle(&ctx, &job.payload.0, params)))) => {
+                    result.unwrap_or_else(|panic| Err(JobError::panic(panic)))
+                }
+                _ = shutdown::requested(shutdown.clone(), Shutdown::Hard) => {
+                    ctx.record_logs().await.expect("could not record the job's logs");
+                    let unstarted: Vec<(i64, i32)> = jobs.by_ref().map(|job| (job.id, job.attempts)).collect();
+                    store.release(&[(job.id, job.attempts)], true)
+                        .await
+                        .expect("could not release jobs");
+                    store.release(&unstarted, false)
+                        .await
+                        .expect("could not release jobs");
+                    store.end_batch().await.expect("could not end the batch");
+                    println!("Released job #{} and {} unstarted job(s)", job.id, unstarted.len());
+                    return;
+                }
+            };
+
+            let failed = result.err().map(|err| {
+                let policy = RetryPolicy::new(job.max_attempts, job.retry_backoff_secs);
+                let retry_in = retry_in(&err, ctx.attempt(), &policy);
+                match retry_in {
+                    Some(secs) => ctx.log(&format!("failed ({}), retrying in {}s", err, secs)),
+                    None => ctx.log(&format!("failed ({}), giving up", err)),
+                }
+                (err, retry_in)
+            });
+            ctx.record_logs()
+                .await
+                .expect("could not record the job's logs");
+            let recorded = match failed {
+                None => store.finish(job.id, ctx.attempt(), JobStatus::Done).await,
+                Some((err, retry_in)) => store.fail(job.id, ctx.attempt(), &err, retry_in).await,
+            }
+            .expect("could not update the job status");
+            if !recorded {
+                ctx.log("outcome not recorded: the job is no longer running under this attempt, its lease ran out");
+            }
+        }
+
+        store.end_batch().await.expect("could not end the batch");
+    }
+
+    println!("Stopped.");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory_store::MemoryJobStore;
+    use crate::store::QueueShares;
+    use crate::JobRow;
+
+    /// A `MemoryJobStore` asking the worker to stop as soon as it starts a
+    /// job. On a hard stop, the job at hand never gets past its first effect,
+    /// as if the mail server hung.
+    struct Stopping {
+        store: MemoryJobStore,
+        shutdown: watch::Sender<Shutdown>,
+        level: Shutdown,
+        claims: Cell<usize>,
+    }
+
+    impl Stopping {
+        fn new(level: Shutdown, jobs: usize) -> (Self, watch::Receiver<Shutdown>) {
+            let store = MemoryJobStore::default();
+            for _ in 0..jobs {
+                store.enqueue(
+                    Payload::SendEmail {
+                        email: "user@example.com".to_string(),
+                        template: None,
+                        attachments: vec![],
+                    },
+                    None,
+                );
+            }
+            let (shutdown, receiver) = watch::channel(Shutdown::Not);
+            let stopping = Stopping {
+                store,
+                shutdown,
+                level,
+                claims: Cell::new(0),
+            };
+            (stopping, receiver)
+        }
+
+        fn state(&self) -> Vec<(JobStatus, i32)> {
+            self.store
+                .rows()
+                .iter()
+                .map(|row| (row.status, row.attempts))
+                .collect()
+        }
+    }
+
+    impl JobStore for Stopping {
+        async fn claim(
+            &self,
+            batch_size: i64,
+            job_types: &[String],
+            min_priority: Option<i32>,
+            queues: &QueueShares,
+        ) -> Result<Vec<JobRow>, sqlx::Error> {
+            self.claims.set(self.claims.get() + 1);
+            self.store
+                .claim(batch_size, job_types, min_priority, queues)
+                .await
+        }
+
+        async fn reap(&self) -> Result<u64, sqlx::Error> {
+            self.store.reap().await
+        }
+
+        async fn release(&self, jobs: &[(i64, i32)], started: bool) -> Result<(), sqlx::Error> {
+            self.store.release(jobs, started).await
+        }
+
+        async fn finish(
+            &self,
+            job_id: i64,
+            attempt: i32,
+            status: JobStatus,
+        ) -> Result<bool, sqlx::Error> {
+            self.store.finish(job_id, attempt, status).await
+        }
+
+        async fn fail(
+            &self,
+            job_id: i64,
+            attempt: i32,
+            error: &JobError,
+            retry_in: Option<f64>,
+        ) -> Result<bool, sqlx::Error> {
+            self.store.fail(job_id, attempt, error, retry_in).await
+        }
+
+        async fn checkpoint(
+            &self,
+            job_id: i64,
+            attempt: i32,
+            state: Value,
+        ) -> Result<bool, sqlx::Error> {
+            self.store.checkpoint(job_id, attempt, state).await
+        }
+
+        async fn enqueue_child(
+            &self,
+            parent_job_id: i64,
+            correlation_id: Option<&str>,
+            payload: &Payload,
+            params: Option<&Params>,
+        ) -> Result<i64, sqlx::Error> {
+            self.store
+                .enqueue_child(parent_job_id, correlation_id, payload, params)
+                .await
+        }
+
+        async fn effect(&self, job_id: i64, key: &str) -> Result<Option<Value>, sqlx::Error> {
+            if self.level == Shutdown::Hard {
+                std::future::pending::<()>().await;
+            }
+            self.store.effect(job_id, key).await
+        }
+
+        async fn record_effect(
+            &self,
+            job_id: i64,
+            key: &str,
+            result: Value,
+        ) -> Result<(), sqlx::Error> {
+            self.store.record_effect(job_id, key, result).await
+        }
+
+        async fn blob(&self, blob_id: i64) -> Result<Option<Vec<u8>>, sqlx::Error> {
+            self.store.blob(blob_id).await
+        }
+
+        async fn record_logs(
+            &self,
+            job_id: i64,
+            attempt: i32,
+            lines: &[String],
+        ) -> Result<(), sqlx::Error> {
+            self.store.record_logs(job_id, attempt, lines).await
+        }
+
+        async fn start(&self, job_id: i64, attempt: i32) -> Result<bool, sqlx::Error> {
+            let started = self.store.start(job_id, attempt).await?;
+            self.shutdown.send_replace(self.level);
+            Ok(started)
+        }
+    }
+
+    fn config(batch_size: i64) -> watch::Receiver<WorkerConfig> {
+        let config = WorkerConfig {
+            batch_size,
+            ..WorkerConfig::default()
+        };
+        watch::channel(config).1
+    }
+
+    #[tokio::test]
+    async fn soft_stop_finishes_the_job_at_hand_and_claims_no_more() {
+        let (store, shutdown) = Stopping::new(Shutdown::Soft, 3);
+        run_until(&store, config(2), shutdown).await;
+
+        assert_eq!(store.claims.get(), 1);
+        assert_eq!(
+            store.state(),
+            vec![
+                (JobStatus::Done, 1),
+                // Handed back unstarted, not counting as an attempt.
+                (JobStatus::Queued, 0),
+                // Never claimed.
+                (JobStatus::Queued, 0),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn hard_stop_releases_the_job_at_hand() {
+        let (store, shutdown) = Stopping::new(Shutdown::Hard, 3);
+        run_until(&store, config(2), shutdown).await;
+
+        assert_eq!(store.claims.get(), 1);
+        assert_eq!(
+            store.state(),
+            vec![
+                // Started, so its attempt counts.
+                (JobStatus::Queued, 1),
+                (JobStatus::Queued, 0),
+                (JobStatus::Queued, 0),
+            ]
+        );
+    }
+
+    /// The context of a freshly claimed job.
+    async fn context(
+        store: &MemoryJobStore,
+        timeout: Option<Duration>,
+    ) -> JobContext<'_, MemoryJobStore> {
+        let job_id = store.enqueue(Payload::NOOP, None);
+        store
+            .claim(1, &[], None, &QueueShares::default())
+            .await
+            .unwrap();
+        let started = Instant::now();
+        JobContext {
+            store,
+            job_id,
+            correlation_id: None,
+            checkpoint: None,
+            attempt: 1,
+            lease_end: Cell::new(lease_end(started)),
+            timeout_at: timeout.map(|timeout| started + timeout),
+            logs: RefCell::new(vec![]),
+        }
+    }
+
+    #[tokio::test]
+    async fn deadline_is_the_timeout_when_it_comes_first() {
+        let store = MemoryJobStore::default();
+        let ctx = context(&store, Some(Duration::from_secs(10))).await;
+        let timeout_at = ctx.timeout_at.unwrap();
+        assert_eq!(ctx.deadline(), timeout_at);
+        assert!(ctx.time_remaining() <= Duration::from_secs(10));
+
+        // Renewing the lease doesn't grant more time.
+        ctx.checkpoint(json!({ "sent": 1 })).await.unwrap();
+        assert_eq!(ctx.deadline(), timeout_at);
+    }
+
+    #[tokio::test]
+    async fn deadline_is_the_lease_end_when_it_comes_first() {
+        let store = MemoryJobStore::default();
+        let ctx = context(&store, Some(Duration::from_secs_f64(LEASE_SECS * 2.0))).await;
+        let first_lease_end = ctx.lease_end.get();
+        assert_eq!(ctx.deadline(), first_lease_end);
+
+        ctx.checkpoint(json!({ "sent": 1 })).await.unwrap();
+        assert!(ctx.deadline() >= first_lease_end);
+        assert!(ctx.deadline() < ctx.timeout_at.unwrap());
+
+        let ctx = context(&store, None).await;
+        assert_eq!(ctx.deadline(), ctx.lease_end.get());
+    }
+}
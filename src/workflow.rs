@@ -0,0 +1,201 @@
+use serde::Deserialize;
+use serde::Serialize;
+use serde_json::json;
+use sqlx::types::Json;
+use sqlx::PgPool;
+use sqlx::Postgres;
+use sqlx::Transaction;
+
+use crate::query_log::logged;
+use crate::JobStatus;
+use crate::Params;
+use crate::Payload;
+
+#[derive(sqlx::Type, Serialize, Debug)]
+#[sqlx(type_name = "WORKFLOW_STATUS")]
+pub enum WorkflowStatus {
+    Running,
+    Completed,
+    Failed,
+    Compensating,
+    Compensated,
+}
+
+/// A workflow is a sequence of steps, each one being enqueued as a job once the
+/// previous one is done. The steps are stored on the workflow row, so the
+/// engine never needs the code that declared them.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Step {
+    pub payload: Payload,
+    pub params: Option<Params>,
+    /// How long to wait after the previous step before running this one.
+    pub delay_secs: u32,
+    /// Undoes the effects of this step, should a later step fail.
+    pub compensation: Option<Compensation>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Compensation {
+    pub payload: Payload,
+    pub params: Option<Params>,
+}
+
+/// Persists a new workflow and enqueues its first step.
+pub async fn start(pg_pool: &PgPool, name: &str, steps: Vec<Step>) -> Result<i64, sqlx::Error> {
+    let first = steps.first().expect("a workflow needs at least one step");
+
+    let mut tx = pg_pool.begin().await?;
+
+    let workflow_id = sqlx::query_scalar!(
+        "INSERT INTO workflows (name, steps) VALUES ($1, $2) RETURNING id",
+        name,
+        json!(steps),
+    )
+    .fetch_one(&mut tx)
+    .await?;
+
+    enqueue_step(&mut tx, workflow_id, 0, first).await?;
+
+    tx.commit().await?;
+    Ok(workflow_id)
+}
+
+/// Steps and compensations go to the `default` queue, with its settings.
+async fn enqueue_step(
+    tx: &mut Transaction<'_, Postgres>,
+    workflow_id: i64,
+    index: i32,
+    step: &Step,
+) -> Result<i64, sqlx::Error> {
+    let status = JobStatus::Queued;
+    let payload = json!(step.payload);
+    let params = step.params.as_ref().map(|params| json!(params));
+    let delay_secs = f64::from(step.delay_secs);
+    logged!(query_scalar!(
+        r#"
+        INSERT INTO jobs (status, payload, params, run_at, workflow_id, workflow_step, tenant, priority, max_attempts, retry_backoff_secs, timeout_secs)
+        SELECT $1, $2, $3, now() + make_interval(secs => $4), $5, $6,
+               COALESCE(settings.tenant, 'default'), COALESCE(settings.priority, 0), settings.max_attempts, settings.retry_backoff_secs, settings.timeout_secs
+        FROM (VALUES ('default')) AS target(queue)
+        LEFT JOIN queue_settings settings USING (queue)
+        RETURNING id
+        "#,
+        status as JobStatus,
+        payload,
+        params,
+        delay_secs,
+        workflow_id,
+        index,
+    ))
+    .run(|query| query.fetch_one(tx))
+    .await
+}
+
+async fn enqueue_compensation(
+    tx: &mut Transaction<'_, Postgres>,
+    workflow_id: i64,
+    index: i32,
+    compensation: &Compensation,
+) -> Result<i64, sqlx::Error> {
+    let status = JobStatus::Queued;
+    let payload = json!(compensation.payload);
+    let params = compensation.params.as_ref().map(|params| json!(params));
+    logged!(query_scalar!(
+        r#"
+        INSERT INTO jobs (status, payload, params, workflow_id, workflow_step, workflow_compensation, tenant, priority, max_attempts, retry_backoff_secs, timeout_secs)
+        SELECT $1, $2, $3, $4, $5, true,
+               COALESCE(settings.tenant, 'default'), COALESCE(settings.priority, 0), settings.max_attempts, settings.retry_backoff_secs, settings.timeout_secs
+        FROM (VALUES ('default')) AS target(queue)
+        LEFT JOIN queue_settings settings USING (queue)
+        RETURNING id
+        "#,
+        status as JobStatus,
+        payload,
+        params,
+        workflow_id,
+        index,
+    ))
+    .run(|query| query.fetch_one(tx))
+    .await
+}
+
+/// Compensations run one at a time, from the latest completed step backwards.
+/// Returns the closest step before `index` that declares one.
+fn previous_compensation(steps: &[Step], index: i32) -> Option<(i32, &Compensation)> {
+    steps
+        .iter()
+        .enumerate()
+        .take(index.max(0) as usize)
+        .rev()
+        .find_map(|(index, step)| {
+            step.compensation
+                .as_ref()
+                .map(|compensation| (index as i32, compensation))
+        })
+}
+
+/// Moves the workflow of a job (if any) forward, within the transaction that
+/// records the job outcome: a done step enqueues the next one, a failed step
+/// starts compensating the steps that already ran.
+pub async fn on_job_finished(
+    tx: &mut Transaction<'_, Postgres>,
+    job_id: i64,
+    status: JobStatus,
+) -> Result<(), sqlx::Error> {
+    let workflow = logged!(query!(
+        r#"
+        SELECT workflows.id,
+               jobs.workflow_step AS "step!",
+               jobs.workflow_compensation AS compensation,
+               workflows.steps AS "steps: Json<Vec<Step>>"
+        FROM jobs
+        JOIN workflows ON workflows.id = jobs.workflow_id
+        WHERE jobs.id = $1
+        FOR UPDATE OF workflows
+        "#,
+        job_id,
+    ))
+    .run(|query| query.fetch_optional(&mut *tx))
+    .await?;
+
+    let workflow = match workflow {
+        Some(workflow) => workflow,
+        None => return Ok(()),
+    };
+    let steps = &workflow.steps.0;
+
+    let (workflow_status, current_step) = match (workflow.compensation, status) {
+        (false, JobStatus::Done) => {
+            let next = workflow.step + 1;
+            match steps.get(next as usize) {
+                Some(step) => {
+                    enqueue_step(tx, workflow.id, next, step).await?;
+                    (WorkflowStatus::Running, next)
+                }
+                None => (WorkflowStatus::Completed, next),
+            }
+        }
+        (true, JobStatus::Done) | (false, _) => match previous_compensation(steps, workflow.step) {
+            Some((index, compensation)) => {
+                enqueue_compensation(tx, workflow.id, index, compensation).await?;
+                (WorkflowStatus::Compensating, index)
+            }
+            None if workflow.compensation => (WorkflowStatus::Compensated, workflow.step),
+            None => (WorkflowStatus::Failed, workflow.step),
+        },
+        // A failed compensation cannot be compensated in turn, a human has to step in.
+        (true, _) => (WorkflowStatus::Failed, workflow.step),
+    };
+
+    let workflow_id = workflow.id;
+    logged!(query!(
+        "UPDATE workflows SET status = $1, current_step = $2 WHERE id = $3",
+        workflow_status as WorkflowStatus,
+        current_step,
+        workflow_id,
+    ))
+    .run(|query| query.execute(&mut *tx))
+    .await?;
+
+    Ok(())
+}